@@ -0,0 +1,490 @@
+//! Dice notation.
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use rand::Rng;
+use serde;
+
+/// Maximum number of rerolls for a single exploding die.
+///
+/// Capping the explosions keeps the probability distributions finite. Both rolling and the
+/// distributions follow the cap, so the distributions stay exact.
+pub const MAX_EXPLOSIONS: u32 = 10;
+
+/// Maximum number of dice in a single roll term.
+///
+/// Dice often come from data files, the limits keep rolling and computing the distributions
+/// cheap for any expression that parses.
+pub const MAX_DICE: u32 = 100;
+
+/// Maximum number of sides of a die.
+pub const MAX_SIDES: u32 = 1000;
+
+/// A dice expression like `3d6+2`.
+///
+/// The notation is a sum of dice rolls and constants, joined by `+` or `-`. A dice roll is the
+/// number of dice, `d` and the number of sides, with the following variations:
+///
+/// * The number of dice can be left out for a single die, `d8`.
+/// * `d%` is the same as `d100`.
+/// * `dF` is a Fudge die with the sides -1, 0 and 1.
+/// * `!` after the die makes it explode. When a die rolls its highest value, it is rolled again
+///   and the new roll is added to it, up to `MAX_EXPLOSIONS` times.
+/// * `khN` keeps the `N` highest dice and drops the rest, `klN` keeps the lowest ones. `kN` is
+///   the same as `khN`.
+///
+/// Rolls of more than `MAX_DICE` dice, dice with more than `MAX_SIDES` sides and expressions
+/// whose results could go outside the range of `i32` are rejected when parsing.
+///
+/// # Examples
+///
+/// ```
+/// use calx_alg::Dice;
+///
+/// let dice: Dice = "2d20kh1".parse().unwrap();
+/// assert_eq!(1, dice.min());
+/// assert_eq!(20, dice.max());
+/// assert_eq!("3d6+2", "3d6 + 2".parse::<Dice>().unwrap().to_string());
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Dice {
+    terms: Vec<Term>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Term {
+    Roll {
+        negative: bool,
+        count: u32,
+        die: Die,
+        explode: bool,
+        keep: Keep,
+    },
+    Constant(i32),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Die {
+    Sides(u32),
+    Fudge,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Keep {
+    All,
+    Highest(u32),
+    Lowest(u32),
+}
+
+impl Dice {
+    /// Roll the dice.
+    pub fn roll<R: Rng>(&self, rng: &mut R) -> i32 {
+        let mut ret = 0;
+        for term in &self.terms {
+            match *term {
+                Term::Constant(x) => ret += x,
+                Term::Roll { negative, count, die, explode, keep } => {
+                    let mut rolls: Vec<i32> = (0..count)
+                                                  .map(|_| roll_die(rng, die, explode))
+                                                  .collect();
+                    rolls.sort();
+                    let kept = match keep {
+                        Keep::All => &rolls[..],
+                        Keep::Highest(n) => &rolls[(count - n) as usize..],
+                        Keep::Lowest(n) => &rolls[..n as usize],
+                    };
+                    let sum: i32 = kept.iter().sum();
+                    ret += if negative { -sum } else { sum };
+                }
+            }
+        }
+        ret
+    }
+
+    /// Return the exact probability of every possible result, sorted by result.
+    pub fn distribution(&self) -> Vec<(i32, f64)> {
+        let mut ret = BTreeMap::new();
+        ret.insert(0, 1.0);
+
+        for term in &self.terms {
+            let dist = match *term {
+                Term::Constant(x) => vec![(x, 1.0)],
+                Term::Roll { negative, count, die, explode, keep } => {
+                    let dist = keep_distribution(&die_distribution(die, explode), count, keep);
+                    if negative {
+                        dist.into_iter().map(|(x, p)| (-x, p)).collect()
+                    } else {
+                        dist
+                    }
+                }
+            };
+
+            let mut sum = BTreeMap::new();
+            for (&x, &p) in &ret {
+                for &(y, q) in &dist {
+                    *sum.entry(x + y).or_insert(0.0) += p * q;
+                }
+            }
+            ret = sum;
+        }
+
+        ret.into_iter().collect()
+    }
+
+    /// Return the expected value of a roll.
+    pub fn mean(&self) -> f64 { self.distribution().iter().map(|&(x, p)| x as f64 * p).sum() }
+
+    /// Return the smallest possible roll.
+    pub fn min(&self) -> i32 { bounds(&self.terms).expect("Dice out of range").0 }
+
+    /// Return the largest possible roll.
+    pub fn max(&self) -> i32 { bounds(&self.terms).expect("Dice out of range").1 }
+}
+
+impl Term {
+    /// Return the smallest and largest value of the term, `None` if they don't fit in `i32`.
+    fn bounds(&self) -> Option<(i32, i32)> {
+        match *self {
+            Term::Constant(x) => Some((x, x)),
+            Term::Roll { negative, count, die, explode, keep } => {
+                let n = match keep {
+                    Keep::All => count,
+                    Keep::Highest(n) | Keep::Lowest(n) => n,
+                };
+                let (lo, hi): (i32, i32) = match die {
+                    Die::Fudge => (-1, 1),
+                    Die::Sides(s) => {
+                        let top = if explode { s.checked_mul(MAX_EXPLOSIONS + 1) } else { Some(s) };
+                        match top {
+                            Some(top) if top <= i32::max_value() as u32 => (1, top as i32),
+                            _ => return None,
+                        }
+                    }
+                };
+                if n > i32::max_value() as u32 {
+                    return None;
+                }
+                let n = n as i32;
+                match (lo.checked_mul(n), hi.checked_mul(n)) {
+                    (Some(lo), Some(hi)) if negative => Some((-hi, -lo)),
+                    (Some(lo), Some(hi)) => Some((lo, hi)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Return the smallest and largest sum of the terms, `None` if they don't fit in `i32`.
+fn bounds(terms: &[Term]) -> Option<(i32, i32)> {
+    let (mut lo, mut hi) = (0i32, 0i32);
+    for term in terms {
+        match term.bounds() {
+            Some((a, b)) => {
+                match (lo.checked_add(a), hi.checked_add(b)) {
+                    (Some(a), Some(b)) => {
+                        lo = a;
+                        hi = b;
+                    }
+                    _ => return None,
+                }
+            }
+            None => return None,
+        }
+    }
+    Some((lo, hi))
+}
+
+fn roll_die<R: Rng>(rng: &mut R, die: Die, explode: bool) -> i32 {
+    match die {
+        Die::Fudge => rng.gen_range(-1, 2),
+        Die::Sides(s) => {
+            let s = s as i32;
+            let mut ret = rng.gen_range(1, s + 1);
+            if explode {
+                let mut last = ret;
+                let mut n = 0;
+                while last == s && n < MAX_EXPLOSIONS {
+                    last = rng.gen_range(1, s + 1);
+                    ret += last;
+                    n += 1;
+                }
+            }
+            ret
+        }
+    }
+}
+
+/// Return the distribution of a single die.
+fn die_distribution(die: Die, explode: bool) -> Vec<(i32, f64)> {
+    match die {
+        Die::Fudge => vec![(-1, 1.0 / 3.0), (0, 1.0 / 3.0), (1, 1.0 / 3.0)],
+        Die::Sides(s) => {
+            let p = 1.0 / s as f64;
+            if !explode {
+                return (1..(s as i32 + 1)).map(|x| (x, p)).collect();
+            }
+
+            let s = s as i32;
+            let mut ret = Vec::new();
+            let mut chain = p;
+            for n in 0..(MAX_EXPLOSIONS as i32 + 1) {
+                // The last roll of the chain can't explode any more, so it can also hit the top.
+                let top = if n == MAX_EXPLOSIONS as i32 { s } else { s - 1 };
+                for x in 1..(top + 1) {
+                    ret.push((n * s + x, chain));
+                }
+                chain *= p;
+            }
+            ret
+        }
+    }
+}
+
+/// Return the distribution of the sum of the kept dice out of `count` dice.
+fn keep_distribution(die: &[(i32, f64)], count: u32, keep: Keep) -> Vec<(i32, f64)> {
+    let n = count as usize;
+    let mut faces = die.to_vec();
+    let k = match keep {
+        Keep::All => n,
+        Keep::Highest(k) => {
+            faces.reverse();
+            k as usize
+        }
+        Keep::Lowest(k) => k as usize,
+    };
+
+    // Go through the faces in the order the dice are kept. The state is the number of dice
+    // assigned to the faces so far and the sum of the kept dice.
+    let mut states: Vec<BTreeMap<i32, f64>> = vec![BTreeMap::new(); n + 1];
+    states[0].insert(0, 1.0);
+    for &(x, p) in &faces {
+        let mut next: Vec<BTreeMap<i32, f64>> = vec![BTreeMap::new(); n + 1];
+        for used in 0..(n + 1) {
+            for (&sum, &prob) in &states[used] {
+                // Ways to pick c of the remaining dice to show this face.
+                let mut ways = 1.0;
+                let mut p_c = 1.0;
+                for c in 0..(n - used + 1) {
+                    if c > 0 {
+                        ways = ways * (n - used - c + 1) as f64 / c as f64;
+                        p_c *= p;
+                    }
+                    let kept = min(c, k.saturating_sub(used)) as i32;
+                    *next[used + c].entry(sum + x * kept).or_insert(0.0) += prob * ways * p_c;
+                }
+            }
+        }
+        states = next;
+    }
+
+    states.pop().unwrap().into_iter().collect()
+}
+
+/// Error from parsing dice notation.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParseDiceError(String);
+
+impl fmt::Display for ParseDiceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.0) }
+}
+
+impl error::Error for ParseDiceError {
+    fn description(&self) -> &str { &self.0 }
+}
+
+impl FromStr for Dice {
+    type Err = ParseDiceError;
+
+    fn from_str(s: &str) -> Result<Dice, ParseDiceError> {
+        let err = |msg: &str| Err(ParseDiceError(format!("{} in '{}'", msg, s)));
+
+        let chars: Vec<char> = s.chars().collect();
+        let mut i = 0;
+        let mut terms = Vec::new();
+
+        // Whitespace is allowed between the terms but not inside them.
+        fn skip_space(chars: &[char], i: &mut usize) {
+            while *i < chars.len() && chars[*i].is_whitespace() {
+                *i += 1;
+            }
+        }
+
+        // Read a number if there is one.
+        fn number(chars: &[char], i: &mut usize) -> Option<u32> {
+            let start = *i;
+            while *i < chars.len() && chars[*i].is_digit(10) {
+                *i += 1;
+            }
+            if *i == start {
+                return None;
+            }
+            chars[start..*i].iter().cloned().collect::<String>().parse().ok()
+        }
+
+        loop {
+            skip_space(&chars, &mut i);
+            let negative = match chars.get(i) {
+                Some(&'-') => {
+                    i += 1;
+                    true
+                }
+                Some(&'+') if !terms.is_empty() => {
+                    i += 1;
+                    false
+                }
+                _ if terms.is_empty() => false,
+                Some(_) => return err("Expected + or -"),
+                None => return err("Unexpected end"),
+            };
+
+            skip_space(&chars, &mut i);
+            let start = i;
+            let count = number(&chars, &mut i);
+            if i > start && count.is_none() {
+                return err("Number too large");
+            }
+
+            if chars.get(i).map_or(false, |&c| c == 'd' || c == 'D') {
+                i += 1;
+                let count = count.unwrap_or(1);
+                if count == 0 {
+                    return err("Zero dice");
+                }
+                if count > MAX_DICE {
+                    return err("Too many dice");
+                }
+
+                let die = match chars.get(i) {
+                    Some(&'F') => {
+                        i += 1;
+                        Die::Fudge
+                    }
+                    Some(&'%') => {
+                        i += 1;
+                        Die::Sides(100)
+                    }
+                    _ => {
+                        match number(&chars, &mut i) {
+                            Some(0) | None => return err("Bad number of sides"),
+                            Some(s) if s > MAX_SIDES => return err("Too many sides"),
+                            Some(s) => Die::Sides(s),
+                        }
+                    }
+                };
+
+                let mut explode = false;
+                let mut keep = Keep::All;
+                loop {
+                    match chars.get(i) {
+                        Some(&'!') if !explode => {
+                            if die == Die::Fudge || die == Die::Sides(1) {
+                                return err("Die can't explode");
+                            }
+                            i += 1;
+                            explode = true;
+                        }
+                        Some(&'k') if keep == Keep::All => {
+                            i += 1;
+                            let lowest = match chars.get(i) {
+                                Some(&'h') => {
+                                    i += 1;
+                                    false
+                                }
+                                Some(&'l') => {
+                                    i += 1;
+                                    true
+                                }
+                                _ => false,
+                            };
+                            let n = match number(&chars, &mut i) {
+                                Some(n) if n <= count => n,
+                                _ => return err("Bad number of dice to keep"),
+                            };
+                            keep = if lowest { Keep::Lowest(n) } else { Keep::Highest(n) };
+                        }
+                        _ => break,
+                    }
+                }
+
+                terms.push(Term::Roll {
+                    negative: negative,
+                    count: count,
+                    die: die,
+                    explode: explode,
+                    keep: keep,
+                });
+            } else {
+                match count {
+                    Some(x) if x <= i32::max_value() as u32 => {
+                        terms.push(Term::Constant(if negative { -(x as i32) } else { x as i32 }))
+                    }
+                    Some(_) => return err("Number too large"),
+                    None => return err("Expected a number or dice"),
+                }
+            }
+
+            // Results must fit in i32 so rolling can't overflow.
+            if bounds(&terms).is_none() {
+                return err("Dice too large");
+            }
+
+            skip_space(&chars, &mut i);
+            if i == chars.len() {
+                return Ok(Dice { terms: terms });
+            }
+        }
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            match *term {
+                Term::Constant(x) => {
+                    if x >= 0 && i > 0 {
+                        write!(f, "+")?;
+                    }
+                    write!(f, "{}", x)?;
+                }
+                Term::Roll { negative, count, die, explode, keep } => {
+                    if negative {
+                        write!(f, "-")?;
+                    } else if i > 0 {
+                        write!(f, "+")?;
+                    }
+                    write!(f, "{}d", count)?;
+                    match die {
+                        Die::Fudge => write!(f, "F")?,
+                        Die::Sides(s) => write!(f, "{}", s)?,
+                    }
+                    if explode {
+                        write!(f, "!")?;
+                    }
+                    match keep {
+                        Keep::All => {}
+                        Keep::Highest(n) => write!(f, "kh{}", n)?,
+                        Keep::Lowest(n) => write!(f, "kl{}", n)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl serde::Serialize for Dice {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.to_string())
+    }
+}
+
+impl serde::Deserialize for Dice {
+    fn deserialize<D: serde::Deserializer>(d: D) -> Result<Self, D::Error> {
+        let s: String = serde::Deserialize::deserialize(d)?;
+        s.parse().map_err(|e: ParseDiceError| serde::de::Error::custom(e.0))
+    }
+}
@@ -1,6 +1,8 @@
 //! Easing functions for animated interpolation between values
 
-use std::f32::consts::FRAC_PI_2;
+use std::cmp::max;
+use std::f32::consts::{FRAC_PI_2, PI};
+use clamp;
 
 pub fn linear(t: f32) -> f32 { t }
 
@@ -32,6 +34,283 @@ pub fn sin_in(t: f32) -> f32 { ((t - 1.0) * FRAC_PI_2).sin() + 1.0 }
 
 pub fn sin_out(t: f32) -> f32 { (t * FRAC_PI_2).sin() }
 
-pub fn sin_in_out(t: f32) -> f32 { 0.5 * (1.0 - (t * FRAC_PI_2).cos()) }
+pub fn sin_in_out(t: f32) -> f32 { 0.5 * (1.0 - (t * PI).cos()) }
 
-// TODO: Elastic easing
+pub fn expo_in(t: f32) -> f32 { if t <= 0.0 { 0.0 } else { 2f32.powf(10.0 * t - 10.0) } }
+
+pub fn expo_out(t: f32) -> f32 { if t >= 1.0 { 1.0 } else { 1.0 - 2f32.powf(-10.0 * t) } }
+
+pub fn expo_in_out(t: f32) -> f32 {
+    if t <= 0.0 {
+        0.0
+    } else if t >= 1.0 {
+        1.0
+    } else if t < 0.5 {
+        2f32.powf(20.0 * t - 10.0) / 2.0
+    } else {
+        (2.0 - 2f32.powf(-20.0 * t + 10.0)) / 2.0
+    }
+}
+
+pub fn circ_in(t: f32) -> f32 { 1.0 - (1.0 - t * t).max(0.0).sqrt() }
+
+pub fn circ_out(t: f32) -> f32 {
+    let u = t - 1.0;
+    (1.0 - u * u).max(0.0).sqrt()
+}
+
+pub fn circ_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        circ_in(2.0 * t) / 2.0
+    } else {
+        (circ_out(2.0 * t - 1.0) + 1.0) / 2.0
+    }
+}
+
+/// Overshoot of the back easing functions.
+const BACK: f32 = 1.70158;
+
+/// Back easing pulls back below zero before moving to the target.
+pub fn back_in(t: f32) -> f32 { (BACK + 1.0) * t * t * t - BACK * t * t }
+
+/// Back easing overshoots the target and then settles back to it.
+pub fn back_out(t: f32) -> f32 {
+    let u = t - 1.0;
+    1.0 + (BACK + 1.0) * u * u * u + BACK * u * u
+}
+
+pub fn back_in_out(t: f32) -> f32 {
+    // Scale the overshoot to be about the same as in the one-sided curves.
+    let c = BACK * 1.525;
+    if t < 0.5 {
+        let u = 2.0 * t;
+        u * u * ((c + 1.0) * u - c) / 2.0
+    } else {
+        let u = 2.0 * t - 2.0;
+        (u * u * ((c + 1.0) * u + c) + 2.0) / 2.0
+    }
+}
+
+/// Elastic easing wobbles like a spring at the start.
+pub fn elastic_in(t: f32) -> f32 {
+    if t <= 0.0 {
+        0.0
+    } else if t >= 1.0 {
+        1.0
+    } else {
+        -2f32.powf(10.0 * t - 10.0) * ((10.0 * t - 10.75) * 2.0 * PI / 3.0).sin()
+    }
+}
+
+/// Elastic easing wobbles like a spring around the target.
+pub fn elastic_out(t: f32) -> f32 {
+    if t <= 0.0 {
+        0.0
+    } else if t >= 1.0 {
+        1.0
+    } else {
+        2f32.powf(-10.0 * t) * ((10.0 * t - 0.75) * 2.0 * PI / 3.0).sin() + 1.0
+    }
+}
+
+pub fn elastic_in_out(t: f32) -> f32 {
+    let phase = ((20.0 * t - 11.125) * 2.0 * PI / 4.5).sin();
+    if t <= 0.0 {
+        0.0
+    } else if t >= 1.0 {
+        1.0
+    } else if t < 0.5 {
+        -2f32.powf(20.0 * t - 10.0) * phase / 2.0
+    } else {
+        2f32.powf(-20.0 * t + 10.0) * phase / 2.0 + 1.0
+    }
+}
+
+/// Bounce easing bounces off the start like a dropped ball in reverse.
+pub fn bounce_in(t: f32) -> f32 { 1.0 - bounce_out(1.0 - t) }
+
+/// Bounce easing bounces against the target like a dropped ball.
+pub fn bounce_out(t: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+    if t < 1.0 / D {
+        N * t * t
+    } else if t < 2.0 / D {
+        let u = t - 1.5 / D;
+        N * u * u + 0.75
+    } else if t < 2.5 / D {
+        let u = t - 2.25 / D;
+        N * u * u + 0.9375
+    } else {
+        let u = t - 2.625 / D;
+        N * u * u + 0.984375
+    }
+}
+
+pub fn bounce_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        (1.0 - bounce_out(1.0 - 2.0 * t)) / 2.0
+    } else {
+        (1.0 + bounce_out(2.0 * t - 1.0)) / 2.0
+    }
+}
+
+/// Cubic Bézier easing like the CSS `cubic-bezier` timing function.
+///
+/// The curve goes from (0, 0) to (1, 1) with the control points (x1, y1) and (x2, y2). The x
+/// coordinates must be in [0, 1] so that the curve is a function of t and are clamped to the
+/// range like CSS does, the y coordinates can go outside the range to make the curve overshoot.
+/// In and out variants of the curve are made by choosing the control points.
+pub fn cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, t: f32) -> f32 {
+    let (x1, x2) = (clamp(0.0, 1.0, x1), clamp(0.0, 1.0, x2));
+    if t <= 0.0 {
+        return 0.0;
+    }
+    if t >= 1.0 {
+        return 1.0;
+    }
+
+    let bezier = |a: f32, b: f32, s: f32| {
+        let u = 1.0 - s;
+        3.0 * u * u * s * a + 3.0 * u * s * s * b + s * s * s
+    };
+    let slope = |a: f32, b: f32, s: f32| {
+        let u = 1.0 - s;
+        3.0 * u * u * a + 6.0 * u * s * (b - a) + 3.0 * s * s * (1.0 - b)
+    };
+
+    // Find the curve parameter for x = t. Newton's method converges fast unless the curve is
+    // flat, fall back to bisection then.
+    let mut s = t;
+    for _ in 0..8 {
+        let err = bezier(x1, x2, s) - t;
+        if err.abs() < 1e-6 {
+            return bezier(y1, y2, s);
+        }
+        let d = slope(x1, x2, s);
+        if d.abs() < 1e-6 {
+            break;
+        }
+        s -= err / d;
+    }
+
+    let (mut lo, mut hi) = (0.0, 1.0);
+    s = t;
+    for _ in 0..32 {
+        let x = bezier(x1, x2, s);
+        if (x - t).abs() < 1e-6 {
+            break;
+        }
+        if x < t {
+            lo = s;
+        } else {
+            hi = s;
+        }
+        s = (lo + hi) / 2.0;
+    }
+    bezier(y1, y2, s)
+}
+
+/// Step easing that jumps at the end of each of `n` steps.
+///
+/// Zero steps is treated as one step, so that a step count from data can't produce NaN.
+pub fn steps_in(n: u32, t: f32) -> f32 {
+    let n = max(n, 1) as f32;
+    if t >= 1.0 {
+        1.0
+    } else {
+        (t * n).floor().max(0.0) / n
+    }
+}
+
+/// Step easing that jumps at the start of each of `n` steps.
+pub fn steps_out(n: u32, t: f32) -> f32 { 1.0 - steps_in(n, 1.0 - t) }
+
+/// Step easing that takes `n` steps over the first half and `n` steps over the second half,
+/// holding still in the middle.
+pub fn steps_in_out(n: u32, t: f32) -> f32 {
+    if t < 0.5 {
+        steps_in(n, 2.0 * t) / 2.0
+    } else {
+        (1.0 + steps_out(n, 2.0 * t - 1.0)) / 2.0
+    }
+}
+
+/// A named easing function.
+///
+/// Animation data can name the easing curves it uses with this type.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Easing {
+    Linear,
+    QuadraticIn,
+    QuadraticOut,
+    QuadraticInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SinIn,
+    SinOut,
+    SinInOut,
+    ExpoIn,
+    ExpoOut,
+    ExpoInOut,
+    CircIn,
+    CircOut,
+    CircInOut,
+    BackIn,
+    BackOut,
+    BackInOut,
+    ElasticIn,
+    ElasticOut,
+    ElasticInOut,
+    BounceIn,
+    BounceOut,
+    BounceInOut,
+    /// Cubic Bézier curve with the control points (x1, y1, x2, y2).
+    CubicBezier(f32, f32, f32, f32),
+    StepsIn(u32),
+    StepsOut(u32),
+    StepsInOut(u32),
+}
+
+impl Easing {
+    /// Apply the easing function to t in [0, 1].
+    pub fn apply(&self, t: f32) -> f32 {
+        use self::Easing::*;
+        match *self {
+            Linear => linear(t),
+            QuadraticIn => quadratic_in(t),
+            QuadraticOut => quadratic_out(t),
+            QuadraticInOut => quadratic_in_out(t),
+            CubicIn => cubic_in(t),
+            CubicOut => cubic_out(t),
+            CubicInOut => cubic_in_out(t),
+            SinIn => sin_in(t),
+            SinOut => sin_out(t),
+            SinInOut => sin_in_out(t),
+            ExpoIn => expo_in(t),
+            ExpoOut => expo_out(t),
+            ExpoInOut => expo_in_out(t),
+            CircIn => circ_in(t),
+            CircOut => circ_out(t),
+            CircInOut => circ_in_out(t),
+            BackIn => back_in(t),
+            BackOut => back_out(t),
+            BackInOut => back_in_out(t),
+            ElasticIn => elastic_in(t),
+            ElasticOut => elastic_out(t),
+            ElasticInOut => elastic_in_out(t),
+            BounceIn => bounce_in(t),
+            BounceOut => bounce_out(t),
+            BounceInOut => bounce_in_out(t),
+            CubicBezier(x1, y1, x2, y2) => cubic_bezier(x1, y1, x2, y2, t),
+            StepsIn(n) => steps_in(n, t),
+            StepsOut(n) => steps_out(n, t),
+            StepsInOut(n) => steps_in_out(n, t),
+        }
+    }
+}
+
+impl Default for Easing {
+    fn default() -> Easing { Easing::Linear }
+}
@@ -1,19 +1,33 @@
+extern crate calx_color;
 extern crate num;
 extern crate rand;
 extern crate time;
 extern crate vec_map;
 extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 use rand::Rng;
 use std::ops::{Add, Mul, Sub};
 use num::Float;
 
-pub use rng::{EncodeRng, RandomPermutation, RngExt};
-pub use text::{LineSplit, split_line};
-
+pub use dice::{Dice, MAX_DICE, MAX_EXPLOSIONS, MAX_SIDES, ParseDiceError};
+pub use noise::{DomainWarp, Fbm, Noise, Perlin, Ridged, Simplex, ValueNoise};
+pub use rng::{Pcg32, PseudoRandom, RandomPermutation, RngExt, Seed, SeedLabel, ShuffleBag,
+              Xoshiro256StarStar};
+pub use scheduler::{EventId, NORMAL_SPEED, Scheduler, Turn};
+pub use text::{LineSplit, MarkupError, TextSpan, parse_markup, split_line, split_spans};
+pub use tween::{Keyframe, KeyframeTrack, Playback, Tween, TweenEvent};
+pub use weighted::{TableEntry, WeightedTable};
+
+mod dice;
+mod noise;
 mod parser;
 mod rng;
+mod scheduler;
 mod text;
+mod tween;
+mod weighted;
 pub mod timing;
 
 pub mod ease;
@@ -0,0 +1,422 @@
+//! Coherent noise functions.
+//!
+//! All noise is generated from a seeded permutation table with plain integer and floating point
+//! arithmetic, so the same seed gives the same values on every platform.
+//!
+//! To sample a hex map, convert the hex cell positions into the Cartesian positions of the cell
+//! centers first so that the noise isn't skewed along the hex axes.
+
+use std::f32::consts::{FRAC_1_SQRT_2, SQRT_2};
+use std::num::Wrapping;
+
+/// A noise function over 2D and 3D space.
+///
+/// The noise values are roughly in the range [-1, 1].
+pub trait Noise {
+    fn noise2(&self, x: f32, y: f32) -> f32;
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32;
+}
+
+impl<'a, N: Noise> Noise for &'a N {
+    fn noise2(&self, x: f32, y: f32) -> f32 { (*self).noise2(x, y) }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 { (*self).noise3(x, y, z) }
+}
+
+/// Seeded permutation of the numbers from 0 to 255, stored twice to avoid wrapping indices.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Permutation(Vec<u8>);
+
+impl Permutation {
+    fn new(seed: u32) -> Permutation {
+        let mut table: Vec<u8> = (0..256).map(|x| x as u8).collect();
+
+        // Fisher-Yates shuffle with a PCG style generator, not using an external RNG keeps the
+        // tables stable.
+        let mut state = Wrapping(seed as u64) + Wrapping(0x853c49e6748fea9b);
+        for i in (1..256).rev() {
+            state = state * Wrapping(6364136223846793005) + Wrapping(1442695040888963407);
+            let Wrapping(s) = state;
+            let x = (((s >> 18) ^ s) >> 27) as u32;
+            let rot = (s >> 59) as u32;
+            let r = x.rotate_right(rot);
+            table.swap(i, r as usize % (i + 1));
+        }
+
+        let mut ret = table.clone();
+        ret.extend(table);
+        Permutation(ret)
+    }
+
+    fn hash2(&self, x: i32, y: i32) -> usize {
+        let p = &self.0;
+        p[p[(x & 255) as usize] as usize + (y & 255) as usize] as usize
+    }
+
+    fn hash3(&self, x: i32, y: i32, z: i32) -> usize {
+        let p = &self.0;
+        p[self.hash2(x, y) + (z & 255) as usize] as usize
+    }
+}
+
+fn floor(x: f32) -> i32 { x.floor() as i32 }
+
+/// Perlin's quintic smoothstep.
+fn fade(t: f32) -> f32 { t * t * t * (t * (t * 6.0 - 15.0) + 10.0) }
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 { a + (b - a) * t }
+
+/// Gradient directions for 2D noise.
+static GRAD2: [(f32, f32); 8] = [(1.0, 0.0),
+                                 (-1.0, 0.0),
+                                 (0.0, 1.0),
+                                 (0.0, -1.0),
+                                 (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+                                 (-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+                                 (FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+                                 (-FRAC_1_SQRT_2, -FRAC_1_SQRT_2)];
+
+/// Gradient directions for 3D noise, the midpoints of the edges of a cube.
+static GRAD3: [(f32, f32, f32); 12] = [(1.0, 1.0, 0.0),
+                                       (-1.0, 1.0, 0.0),
+                                       (1.0, -1.0, 0.0),
+                                       (-1.0, -1.0, 0.0),
+                                       (1.0, 0.0, 1.0),
+                                       (-1.0, 0.0, 1.0),
+                                       (1.0, 0.0, -1.0),
+                                       (-1.0, 0.0, -1.0),
+                                       (0.0, 1.0, 1.0),
+                                       (0.0, -1.0, 1.0),
+                                       (0.0, 1.0, -1.0),
+                                       (0.0, -1.0, -1.0)];
+
+fn grad2(hash: usize, x: f32, y: f32) -> f32 {
+    let (gx, gy) = GRAD2[hash % 8];
+    gx * x + gy * y
+}
+
+fn grad3(hash: usize, x: f32, y: f32, z: f32) -> f32 {
+    let (gx, gy, gz) = GRAD3[hash % 12];
+    gx * x + gy * y + gz * z
+}
+
+/// Perlin gradient noise.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Perlin {
+    perm: Permutation,
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Perlin { Perlin { perm: Permutation::new(seed) } }
+}
+
+impl Noise for Perlin {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        let (xi, yi) = (floor(x), floor(y));
+        let (x, y) = (x - xi as f32, y - yi as f32);
+        let (u, v) = (fade(x), fade(y));
+
+        let g = |i, j| grad2(self.perm.hash2(xi + i, yi + j), x - i as f32, y - j as f32);
+        // Scale the diagonal maximum of 1/sqrt(2) to 1.
+        lerp(lerp(g(0, 0), g(1, 0), u), lerp(g(0, 1), g(1, 1), u), v) * SQRT_2
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (xi, yi, zi) = (floor(x), floor(y), floor(z));
+        let (x, y, z) = (x - xi as f32, y - yi as f32, z - zi as f32);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let g = |i, j, k| {
+            grad3(self.perm.hash3(xi + i, yi + j, zi + k),
+                  x - i as f32,
+                  y - j as f32,
+                  z - k as f32)
+        };
+        lerp(lerp(lerp(g(0, 0, 0), g(1, 0, 0), u),
+                  lerp(g(0, 1, 0), g(1, 1, 0), u),
+                  v),
+             lerp(lerp(g(0, 0, 1), g(1, 0, 1), u),
+                  lerp(g(0, 1, 1), g(1, 1, 1), u),
+                  v),
+             w)
+    }
+}
+
+/// Simplex gradient noise.
+///
+/// Has fewer directional artifacts than Perlin noise and is faster in 3D.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Simplex {
+    perm: Permutation,
+}
+
+impl Simplex {
+    pub fn new(seed: u32) -> Simplex { Simplex { perm: Permutation::new(seed) } }
+}
+
+impl Noise for Simplex {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        // Skewing factors (sqrt(3) - 1) / 2 and (3 - sqrt(3)) / 6.
+        const F2: f32 = 0.3660254;
+        const G2: f32 = 0.21132487;
+
+        let s = (x + y) * F2;
+        let (i, j) = (floor(x + s), floor(y + s));
+        let t = (i + j) as f32 * G2;
+        let (x0, y0) = (x - (i as f32 - t), y - (j as f32 - t));
+
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let corners = [(0, 0, x0, y0),
+                       (i1, j1, x0 - i1 as f32 + G2, y0 - j1 as f32 + G2),
+                       (1, 1, x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2)];
+
+        let mut n = 0.0;
+        for &(di, dj, x, y) in &corners {
+            let t = 0.5 - x * x - y * y;
+            if t > 0.0 {
+                let t = t * t;
+                n += t * t * grad2(self.perm.hash2(i + di, j + dj), x, y);
+            }
+        }
+        n * 99.2
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        const F3: f32 = 1.0 / 3.0;
+        const G3: f32 = 1.0 / 6.0;
+
+        let s = (x + y + z) * F3;
+        let (i, j, k) = (floor(x + s), floor(y + s), floor(z + s));
+        let t = (i + j + k) as f32 * G3;
+        let (x0, y0, z0) = (x - (i as f32 - t), y - (j as f32 - t), z - (k as f32 - t));
+
+        // Find the simplex the point is in.
+        let ((i1, j1, k1), (i2, j2, k2)) = if x0 >= y0 {
+            if y0 >= z0 {
+                ((1, 0, 0), (1, 1, 0))
+            } else if x0 >= z0 {
+                ((1, 0, 0), (1, 0, 1))
+            } else {
+                ((0, 0, 1), (1, 0, 1))
+            }
+        } else if y0 < z0 {
+            ((0, 0, 1), (0, 1, 1))
+        } else if x0 < z0 {
+            ((0, 1, 0), (0, 1, 1))
+        } else {
+            ((0, 1, 0), (1, 1, 0))
+        };
+
+        let corners = [(0, 0, 0, x0, y0, z0),
+                       (i1,
+                        j1,
+                        k1,
+                        x0 - i1 as f32 + G3,
+                        y0 - j1 as f32 + G3,
+                        z0 - k1 as f32 + G3),
+                       (i2,
+                        j2,
+                        k2,
+                        x0 - i2 as f32 + 2.0 * G3,
+                        y0 - j2 as f32 + 2.0 * G3,
+                        z0 - k2 as f32 + 2.0 * G3),
+                       (1, 1, 1, x0 - 1.0 + 3.0 * G3, y0 - 1.0 + 3.0 * G3, z0 - 1.0 + 3.0 * G3)];
+
+        let mut n = 0.0;
+        for &(di, dj, dk, x, y, z) in &corners {
+            let t = 0.6 - x * x - y * y - z * z;
+            if t > 0.0 {
+                let t = t * t;
+                n += t * t * grad3(self.perm.hash3(i + di, j + dj, k + dk), x, y, z);
+            }
+        }
+        n * 32.0
+    }
+}
+
+/// Value noise, smoothly interpolated random values at integer lattice points.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ValueNoise {
+    perm: Permutation,
+}
+
+impl ValueNoise {
+    pub fn new(seed: u32) -> ValueNoise { ValueNoise { perm: Permutation::new(seed) } }
+}
+
+fn lattice_value(hash: usize) -> f32 { hash as f32 / 127.5 - 1.0 }
+
+impl Noise for ValueNoise {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        let (xi, yi) = (floor(x), floor(y));
+        let (u, v) = (fade(x - xi as f32), fade(y - yi as f32));
+
+        let g = |i, j| lattice_value(self.perm.hash2(xi + i, yi + j));
+        lerp(lerp(g(0, 0), g(1, 0), u), lerp(g(0, 1), g(1, 1), u), v)
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (xi, yi, zi) = (floor(x), floor(y), floor(z));
+        let (u, v, w) = (fade(x - xi as f32), fade(y - yi as f32), fade(z - zi as f32));
+
+        let g = |i, j, k| lattice_value(self.perm.hash3(xi + i, yi + j, zi + k));
+        lerp(lerp(lerp(g(0, 0, 0), g(1, 0, 0), u),
+                  lerp(g(0, 1, 0), g(1, 1, 0), u),
+                  v),
+             lerp(lerp(g(0, 0, 1), g(1, 0, 1), u),
+                  lerp(g(0, 1, 1), g(1, 1, 1), u),
+                  v),
+             w)
+    }
+}
+
+/// Offset added to the coordinates of each octave so that the octaves don't all line up at the
+/// origin.
+const OCTAVE_OFFSET: f32 = 19.1731;
+
+/// Fractal Brownian motion, a sum of octaves of noise at increasing frequencies.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Fbm<N> {
+    pub source: N,
+    pub octaves: u32,
+    /// Frequency of the first octave.
+    pub frequency: f32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub gain: f32,
+}
+
+impl<N: Noise> Fbm<N> {
+    pub fn new(source: N) -> Fbm<N> {
+        Fbm {
+            source: source,
+            octaves: 6,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+
+    /// Sum the octaves of a noise function, scaled back to the range of a single octave.
+    fn sum<F: Fn(f32, f32) -> f32>(&self, f: F) -> f32 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        for i in 0..self.octaves {
+            sum += f(frequency, i as f32 * OCTAVE_OFFSET) * amplitude;
+            norm += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if norm > 0.0 { sum / norm } else { 0.0 }
+    }
+}
+
+impl<N: Noise> Noise for Fbm<N> {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        self.sum(|f, o| self.source.noise2(x * f + o, y * f + o))
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.sum(|f, o| self.source.noise3(x * f + o, y * f + o, z * f + o))
+    }
+}
+
+/// Ridged multifractal noise.
+///
+/// Folds the octaves of the source noise into sharp ridges along its zero crossings, good for
+/// mountain ranges and rivers.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Ridged<N> {
+    pub source: N,
+    pub octaves: u32,
+    /// Frequency of the first octave.
+    pub frequency: f32,
+    /// Frequency multiplier between octaves.
+    pub lacunarity: f32,
+    /// Amplitude multiplier between octaves.
+    pub gain: f32,
+}
+
+impl<N: Noise> Ridged<N> {
+    pub fn new(source: N) -> Ridged<N> {
+        Ridged {
+            source: source,
+            octaves: 6,
+            frequency: 1.0,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+
+    fn sum<F: Fn(f32, f32) -> f32>(&self, f: F) -> f32 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = self.frequency;
+        // Weight successive octaves by the previous ones so that the detail shows up on the
+        // ridges and not in the valleys.
+        let mut weight = 1.0;
+        for i in 0..self.octaves {
+            let signal = 1.0 - f(frequency, i as f32 * OCTAVE_OFFSET).abs();
+            let signal = signal * signal * weight;
+            weight = ::clamp(0.0, 1.0, signal * 2.0);
+            sum += signal * amplitude;
+            norm += amplitude;
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        if norm > 0.0 { sum / norm * 2.0 - 1.0 } else { 0.0 }
+    }
+}
+
+impl<N: Noise> Noise for Ridged<N> {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        self.sum(|f, o| self.source.noise2(x * f + o, y * f + o))
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.sum(|f, o| self.source.noise3(x * f + o, y * f + o, z * f + o))
+    }
+}
+
+/// Domain warping, perturbs the sample position of one noise function with another.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DomainWarp<N, W> {
+    pub source: N,
+    pub warp: W,
+    /// Maximum displacement of the sample position.
+    pub strength: f32,
+}
+
+impl<N: Noise, W: Noise> DomainWarp<N, W> {
+    pub fn new(source: N, warp: W, strength: f32) -> DomainWarp<N, W> {
+        DomainWarp {
+            source: source,
+            warp: warp,
+            strength: strength,
+        }
+    }
+}
+
+impl<N: Noise, W: Noise> Noise for DomainWarp<N, W> {
+    fn noise2(&self, x: f32, y: f32) -> f32 {
+        // Sample the warp at distant points to get independent displacements for each axis.
+        let dx = self.warp.noise2(x, y);
+        let dy = self.warp.noise2(x + 5.2, y + 1.3);
+        self.source.noise2(x + dx * self.strength, y + dy * self.strength)
+    }
+
+    fn noise3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let dx = self.warp.noise3(x, y, z);
+        let dy = self.warp.noise3(x + 5.2, y + 1.3, z + 2.8);
+        let dz = self.warp.noise3(x + 1.7, y + 9.2, z + 4.6);
+        self.source.noise3(x + dx * self.strength,
+                           y + dy * self.strength,
+                           z + dz * self.strength)
+    }
+}
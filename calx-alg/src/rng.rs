@@ -1,8 +1,9 @@
-use std::mem;
+use std::f64::consts::PI;
+use std::iter::FromIterator;
 use vec_map::VecMap;
 use rand::{Rng, SeedableRng};
 use serde;
-use to_log_odds;
+use {clamp, to_log_odds};
 
 /// Additional methods for random number generators.
 pub trait RngExt {
@@ -23,6 +24,41 @@ pub trait RngExt {
     /// Return true with the probability corresponding to the log odds with
     /// the given deciban value.
     fn with_log_odds(&mut self, db: f32) -> bool;
+
+    /// Sample the normal distribution.
+    fn normal(&mut self, mean: f32, std_dev: f32) -> f32;
+
+    /// Sample the normal distribution limited to [min, max].
+    ///
+    /// Values outside the range are rejected and sampled again. If the range is so far out in
+    /// the tail that nothing hits it in a thousand tries, the end of the range closest to the
+    /// mean is returned.
+    fn truncated_normal(&mut self, mean: f32, std_dev: f32, min: f32, max: f32) -> f32;
+
+    /// Sample the exponential distribution, the waiting time between events that happen at
+    /// `rate` per unit of time.
+    fn exponential(&mut self, rate: f32) -> f32;
+
+    /// Sample the Poisson distribution, the number of events in a period when `mean` events are
+    /// expected.
+    fn poisson(&mut self, mean: f32) -> u32;
+
+    /// Sample the binomial distribution, the number of successes in `n` tries with the success
+    /// probability `p`.
+    fn binomial(&mut self, n: u32, p: f32) -> u32;
+
+    /// Sample the triangular distribution from [min, max] that peaks at `mode`.
+    fn triangular(&mut self, min: f32, mode: f32, max: f32) -> f32;
+
+    /// Sample the beta distribution on [0, 1] with the shape parameters `a` and `b`.
+    fn beta(&mut self, a: f32, b: f32) -> f32;
+
+    /// Return a uniformly distributed point inside the unit circle.
+    fn in_unit_circle(&mut self) -> (f32, f32);
+
+    /// Return a uniformly distributed point inside the regular hexagon with the corners at unit
+    /// distance from the origin and two corners at (-1, 0) and (1, 0).
+    fn in_unit_hex(&mut self) -> (f32, f32);
 }
 
 impl<T: Rng> RngExt for T {
@@ -35,56 +71,389 @@ impl<T: Rng> RngExt for T {
     fn log_odds(&mut self) -> f32 { to_log_odds(self.gen_range(0.0, 1.0)) }
 
     fn with_log_odds(&mut self, db: f32) -> bool { db > self.log_odds() }
+
+    fn normal(&mut self, mean: f32, std_dev: f32) -> f32 {
+        mean + std_dev * standard_normal(self) as f32
+    }
+
+    fn truncated_normal(&mut self, mean: f32, std_dev: f32, min: f32, max: f32) -> f32 {
+        assert!(min <= max);
+        for _ in 0..1000 {
+            let x = self.normal(mean, std_dev);
+            if x >= min && x <= max {
+                return x;
+            }
+        }
+        clamp(min, max, mean)
+    }
+
+    fn exponential(&mut self, rate: f32) -> f32 {
+        assert!(rate > 0.0);
+        (-open_unit(self).ln() / rate as f64) as f32
+    }
+
+    fn poisson(&mut self, mean: f32) -> u32 {
+        assert!(mean >= 0.0);
+        let mean = mean as f64;
+        if mean < 10.0 {
+            // Multiply uniform numbers until the product drops below e^-mean.
+            let limit = (-mean).exp();
+            let mut product = self.gen::<f64>();
+            let mut ret = 0;
+            while product > limit {
+                product *= self.gen::<f64>();
+                ret += 1;
+            }
+            return ret;
+        }
+
+        // Transformed rejection (PTRS) by Hörmann.
+        let smu = mean.sqrt();
+        let b = 0.931 + 2.53 * smu;
+        let a = -0.059 + 0.02483 * b;
+        let inv_alpha = 1.1239 + 1.1328 / (b - 3.4);
+        let vr = 0.9277 - 3.6224 / (b - 2.0);
+        loop {
+            let u = self.gen::<f64>() - 0.5;
+            let v = self.gen::<f64>();
+            let us = 0.5 - u.abs();
+            let k = ((2.0 * a / us + b) * u + mean + 0.43).floor();
+            if us >= 0.07 && v <= vr {
+                return k as u32;
+            }
+            if k < 0.0 || (us < 0.013 && v > us) {
+                continue;
+            }
+            if v.ln() + inv_alpha.ln() - (a / (us * us) + b).ln() <=
+               -mean + k * mean.ln() - ln_factorial(k) {
+                return k as u32;
+            }
+        }
+    }
+
+    fn binomial(&mut self, n: u32, p: f32) -> u32 {
+        assert!(p >= 0.0 && p <= 1.0);
+        if p > 0.5 {
+            return n - self.binomial(n, 1.0 - p);
+        }
+        if p == 0.0 {
+            return 0;
+        }
+
+        // Skip over the failures between successes with geometrically distributed gaps.
+        let log_q = (1.0 - p as f64).ln();
+        let mut ret = 0;
+        let mut pos = 0.0;
+        loop {
+            pos += (open_unit(self).ln() / log_q).floor() + 1.0;
+            if pos > n as f64 {
+                return ret;
+            }
+            ret += 1;
+        }
+    }
+
+    fn triangular(&mut self, min: f32, mode: f32, max: f32) -> f32 {
+        assert!(min <= mode && mode <= max);
+        let (min, mode, max) = (min as f64, mode as f64, max as f64);
+        if min == max {
+            return min as f32;
+        }
+        let u = self.gen::<f64>();
+        let split = (mode - min) / (max - min);
+        let ret = if u < split {
+            min + (u * (max - min) * (mode - min)).sqrt()
+        } else {
+            max - ((1.0 - u) * (max - min) * (max - mode)).sqrt()
+        };
+        ret as f32
+    }
+
+    fn beta(&mut self, a: f32, b: f32) -> f32 {
+        assert!(a > 0.0 && b > 0.0);
+        let x = standard_gamma(self, a as f64);
+        let y = standard_gamma(self, b as f64);
+        (x / (x + y)) as f32
+    }
+
+    fn in_unit_circle(&mut self) -> (f32, f32) {
+        let r = self.gen::<f64>().sqrt();
+        let a = self.gen::<f64>() * 2.0 * PI;
+        ((r * a.cos()) as f32, (r * a.sin()) as f32)
+    }
+
+    fn in_unit_hex(&mut self) -> (f32, f32) {
+        // The hexagon is made of three rhombuses spanned by adjacent pairs of the unit vectors
+        // at 0, 120 and 240 degrees.
+        let k = self.gen_range(0, 3) as f64;
+        let (u, v) = (self.gen::<f64>(), self.gen::<f64>());
+        let a1 = k * 2.0 * PI / 3.0;
+        let a2 = (k + 1.0) * 2.0 * PI / 3.0;
+        ((u * a1.cos() + v * a2.cos()) as f32, (u * a1.sin() + v * a2.sin()) as f32)
+    }
 }
 
-/// A wrapper that makes a Rng implementation encodable.
-///
-/// For games that want to store the current Rng state as a part of the save
-/// game. Works by casting the Rng representation into a binary blob, will
-/// crash and burn if the Rng struct is not plain-old-data.
-#[derive(Clone, Debug)]
-pub struct EncodeRng<T> {
-    inner: T,
+/// Return a uniform random number from (0, 1].
+fn open_unit<R: Rng>(rng: &mut R) -> f64 { 1.0 - rng.gen::<f64>() }
+
+/// Sample the standard normal distribution with the Box-Muller transform.
+fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let r = (-2.0 * open_unit(rng).ln()).sqrt();
+    r * (2.0 * PI * rng.gen::<f64>()).cos()
 }
 
-impl<T: Rng + 'static> EncodeRng<T> {
-    pub fn new(inner: T) -> EncodeRng<T> { EncodeRng { inner: inner } }
+/// Sample the gamma distribution with unit scale using the method of Marsaglia and Tsang.
+fn standard_gamma<R: Rng>(rng: &mut R, shape: f64) -> f64 {
+    if shape < 1.0 {
+        let u = open_unit(rng);
+        return standard_gamma(rng, shape + 1.0) * u.powf(1.0 / shape);
+    }
+
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = standard_normal(rng);
+        let v = 1.0 + c * x;
+        if v <= 0.0 {
+            continue;
+        }
+        let v = v * v * v;
+        let u = open_unit(rng);
+        if u < 1.0 - 0.0331 * x * x * x * x || u.ln() < 0.5 * x * x + d * (1.0 - v + v.ln()) {
+            return d * v;
+        }
+    }
 }
 
-impl<T: SeedableRng<S> + Rng + 'static, S> SeedableRng<S> for EncodeRng<T> {
-    fn reseed(&mut self, seed: S) { self.inner.reseed(seed); }
+/// Return ln(k!).
+fn ln_factorial(k: f64) -> f64 {
+    if k < 10.0 {
+        return (2..(k as u32 + 1)).map(|i| (i as f64).ln()).sum();
+    }
+    // Stirling series.
+    k * k.ln() - k + 0.5 * (2.0 * PI * k).ln() + 1.0 / (12.0 * k) - 1.0 / (360.0 * k * k * k)
+}
 
-    fn from_seed(seed: S) -> EncodeRng<T> { EncodeRng::new(SeedableRng::from_seed(seed)) }
+/// The PCG32 random number generator.
+///
+/// A small and fast generator from the PCG family by Melissa O'Neill, the XSH RR variant with 64
+/// bits of state and 32 bit output. The output is the same on every platform and the generator
+/// serializes its state explicitly, so it can be stored in save games.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    /// Stream selector, always odd.
+    inc: u64,
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    /// Create a generator with a seed and a stream index.
+    ///
+    /// Generators with different streams produce different sequences from the same seed.
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut ret = Pcg32 {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        ret.step();
+        ret.state = ret.state.wrapping_add(seed);
+        ret.step();
+        ret
+    }
+
+    fn step(&mut self) {
+        self.state = self.state.wrapping_mul(PCG_MULTIPLIER).wrapping_add(self.inc);
+    }
+}
+
+impl Rng for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
 }
 
-impl<T: Rng> Rng for EncodeRng<T> {
-    fn next_u32(&mut self) -> u32 { self.inner.next_u32() }
+impl SeedableRng<u64> for Pcg32 {
+    fn reseed(&mut self, seed: u64) { *self = Pcg32::from_seed(seed); }
+
+    fn from_seed(seed: u64) -> Pcg32 { Pcg32::new(seed, 0) }
 }
 
-impl<T: Rng + 'static> serde::Serialize for EncodeRng<T> {
+impl serde::Serialize for Pcg32 {
     fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
-        let mut vec = Vec::new();
-        unsafe {
-            let view = self as *const _ as *const u8;
-            for i in 0..(mem::size_of::<T>()) {
-                vec.push(*view.offset(i as isize));
-            }
+        serde::Serialize::serialize(&(self.state, self.inc), s)
+    }
+}
+
+impl serde::Deserialize for Pcg32 {
+    fn deserialize<D: serde::Deserializer>(d: D) -> Result<Self, D::Error> {
+        let (state, inc): (u64, u64) = serde::Deserialize::deserialize(d)?;
+        if inc & 1 == 0 {
+            return Err(serde::de::Error::custom("PCG32 increment must be odd"));
+        }
+        Ok(Pcg32 {
+            state: state,
+            inc: inc,
+        })
+    }
+}
+
+/// The xoshiro256** random number generator.
+///
+/// An all-purpose generator by David Blackman and Sebastiano Vigna with 256 bits of state and 64
+/// bit output. Like `Pcg32`, the output is the same on every platform and the state serializes
+/// explicitly.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Xoshiro256StarStar {
+    s: [u64; 4],
+}
+
+impl Xoshiro256StarStar {
+    /// Create a generator from a raw state.
+    ///
+    /// The state must not be all zeroes.
+    pub fn from_state(s: [u64; 4]) -> Xoshiro256StarStar {
+        assert!(s != [0; 4], "xoshiro256** state must not be all zeroes");
+        Xoshiro256StarStar { s: s }
+    }
+}
+
+impl Rng for Xoshiro256StarStar {
+    fn next_u32(&mut self) -> u32 { (self.next_u64() >> 32) as u32 }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let ret = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        ret
+    }
+}
+
+impl SeedableRng<u64> for Xoshiro256StarStar {
+    fn reseed(&mut self, seed: u64) { *self = Xoshiro256StarStar::from_seed(seed); }
+
+    /// Expand the seed into the full state with SplitMix64 as recommended by the authors.
+    fn from_seed(seed: u64) -> Xoshiro256StarStar {
+        let mut x = seed;
+        let mut s = [0; 4];
+        for i in s.iter_mut() {
+            x = x.wrapping_add(0x9e3779b97f4a7c15);
+            *i = mix64(x);
         }
-        vec.serialize(s)
+        Xoshiro256StarStar { s: s }
+    }
+}
+
+impl serde::Serialize for Xoshiro256StarStar {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.s, s)
     }
 }
 
-impl<T: Rng + 'static> serde::Deserialize for EncodeRng<T> {
+impl serde::Deserialize for Xoshiro256StarStar {
     fn deserialize<D: serde::Deserializer>(d: D) -> Result<Self, D::Error> {
-        let blob: Vec<u8> = serde::Deserialize::deserialize(d)?;
-        unsafe {
-            if blob.len() == mem::size_of::<T>() {
-                Ok(EncodeRng::new(mem::transmute_copy(&blob[0])))
-            } else {
-                Err(serde::de::Error::invalid_length(blob.len(), &"Bad inner RNG length"))
-            }
+        let s: [u64; 4] = serde::Deserialize::deserialize(d)?;
+        if s == [0; 4] {
+            return Err(serde::de::Error::custom("xoshiro256** state must not be all zeroes"));
         }
+        Ok(Xoshiro256StarStar { s: s })
+    }
+}
+
+/// A seed for deriving independent random number generator streams.
+///
+/// A root seed is extended with a path of labels, and each distinct path gives its own stream.
+/// The stream for `Seed::new(root).child("level").child(7)` is always the same no matter what
+/// other streams have been used, so different parts of procedural generation don't disturb each
+/// other.
+///
+/// The derivation is part of the stable API. The same root seed and label path will produce the
+/// same random numbers in every future release.
+///
+/// # Examples
+///
+/// ```
+/// use calx_alg::{RngExt, Seed};
+///
+/// let level = Seed::new(1234).child("level").child(7);
+/// let room = level.child("room").child(3);
+/// assert_eq!(room, Seed::new(1234).child("level").child(7).child("room").child(3));
+///
+/// let mut rng = room.rng();
+/// let _ = rng.one_chance_in(6);
+/// ```
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Seed(u64);
+
+impl Seed {
+    pub fn new(root: u64) -> Seed { Seed(root) }
+
+    /// Return the raw value of the seed.
+    pub fn value(&self) -> u64 { self.0 }
+
+    /// Derive the seed for a child path element.
+    pub fn child<L: SeedLabel>(&self, label: L) -> Seed {
+        Seed(mix64(self.0.wrapping_mul(0x9e3779b97f4a7c15) ^ label.label_hash()))
     }
+
+    /// Create a random number generator from the seed.
+    pub fn rng(&self) -> Xoshiro256StarStar { SeedableRng::from_seed(self.0) }
+}
+
+/// A label in a `Seed` derivation path.
+///
+/// String labels hash their UTF-8 bytes and integer labels hash their value, so labels of
+/// different integer types with the same value are the same label. String and integer labels
+/// are hashed differently, so a string label and an integer label lead to independent seeds
+/// with very high probability, but this is not guaranteed.
+pub trait SeedLabel {
+    fn label_hash(&self) -> u64;
+}
+
+impl<'a> SeedLabel for &'a str {
+    fn label_hash(&self) -> u64 {
+        // FNV-1a
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &b in self.as_bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        mix64(hash)
+    }
+}
+
+impl SeedLabel for String {
+    fn label_hash(&self) -> u64 { (&self[..]).label_hash() }
+}
+
+macro_rules! int_seed_label {
+    ($($t:ty),*) => {
+        $(impl SeedLabel for $t {
+            fn label_hash(&self) -> u64 {
+                // Tag the value to keep it apart from string hashes.
+                mix64((*self as i64 as u64) ^ 0x5851f42d4c957f2d).wrapping_add(1)
+            }
+        })*
+    }
+}
+
+int_seed_label!(i8, i16, i32, i64, u8, u16, u32, u64, isize, usize);
+
+/// SplitMix64 output mixing function.
+fn mix64(x: u64) -> u64 {
+    let z = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
 }
 
 /// Lazily evaluated random permutation.
@@ -120,3 +489,156 @@ impl<'a, R: Rng + 'static> Iterator for RandomPermutation<'a, R> {
         ret
     }
 }
+
+/// A bag of items drawn in random order without repeats.
+///
+/// Every item is drawn once before any item is drawn again. The bag refills itself with all of
+/// its items when it runs out, so outcomes can't be streakier than one full bag allows.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate rand;
+/// # extern crate calx_alg;
+/// # fn main() {
+/// use calx_alg::ShuffleBag;
+///
+/// let mut rng = rand::thread_rng();
+/// let mut bag = ShuffleBag::new(vec![1, 2, 3]);
+/// let mut drawn: Vec<i32> = (0..3).map(|_| *bag.draw(&mut rng).unwrap()).collect();
+/// drawn.sort();
+/// assert_eq!(vec![1, 2, 3], drawn);
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ShuffleBag<T> {
+    /// The items, with the already drawn ones at the front.
+    items: Vec<T>,
+    drawn: usize,
+}
+
+impl<T> ShuffleBag<T> {
+    pub fn new(items: Vec<T>) -> ShuffleBag<T> {
+        ShuffleBag {
+            items: items,
+            drawn: 0,
+        }
+    }
+
+    /// Draw an item from the bag.
+    ///
+    /// Returns `None` only if the bag has no items at all.
+    pub fn draw<R: Rng>(&mut self, rng: &mut R) -> Option<&T> {
+        if self.items.is_empty() {
+            return None;
+        }
+        if self.drawn >= self.items.len() {
+            self.refill();
+        }
+
+        let i = rng.gen_range(self.drawn, self.items.len());
+        self.items.swap(self.drawn, i);
+        self.drawn += 1;
+        Some(&self.items[self.drawn - 1])
+    }
+
+    /// Add an item to the undrawn part of the bag.
+    pub fn add(&mut self, item: T) { self.items.push(item); }
+
+    /// Put all the drawn items back into the bag.
+    pub fn refill(&mut self) { self.drawn = 0; }
+
+    /// Return the number of items that can be drawn before the bag refills.
+    pub fn remaining(&self) -> usize { self.items.len().saturating_sub(self.drawn) }
+
+    /// Return the total number of items in the bag.
+    pub fn len(&self) -> usize { self.items.len() }
+
+    pub fn is_empty(&self) -> bool { self.items.is_empty() }
+}
+
+impl<T> FromIterator<T> for ShuffleBag<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> ShuffleBag<T> {
+        ShuffleBag::new(iter.into_iter().collect())
+    }
+}
+
+/// A chance that grows after every failure, with the pseudo-random distribution.
+///
+/// The chance of success on the n-th attempt after the last success is `n * c`, where the
+/// constant `c` is chosen so that the long run success rate equals the nominal chance. Successes
+/// happen about as often as with plain random rolls, but long losing streaks and clusters of
+/// successes are much less likely.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PseudoRandom {
+    increment: f64,
+    failures: u32,
+}
+
+impl PseudoRandom {
+    /// Create a pseudo-random chance with the given nominal probability.
+    pub fn new(chance: f32) -> PseudoRandom {
+        PseudoRandom {
+            increment: prd_increment(chance as f64),
+            failures: 0,
+        }
+    }
+
+    /// Return the chance of success on the next roll.
+    pub fn chance(&self) -> f32 {
+        (self.increment * (self.failures + 1) as f64).min(1.0) as f32
+    }
+
+    /// Roll for success.
+    ///
+    /// A success resets the chance to the starting value, a failure increases it.
+    pub fn roll<R: Rng>(&mut self, rng: &mut R) -> bool {
+        if rng.gen::<f64>() < self.increment * (self.failures + 1) as f64 {
+            self.failures = 0;
+            true
+        } else {
+            self.failures += 1;
+            false
+        }
+    }
+
+    /// Reset the chance to the starting value.
+    pub fn reset(&mut self) { self.failures = 0; }
+}
+
+/// Long run success rate of the pseudo-random distribution with increment `c`.
+fn prd_rate(c: f64) -> f64 {
+    // Rate is one over the expected number of attempts per success.
+    let mut expected = 0.0;
+    let mut no_success_yet = 1.0;
+    let mut n = 1.0;
+    while no_success_yet > 0.0 {
+        let p = (c * n).min(1.0);
+        expected += n * no_success_yet * p;
+        no_success_yet *= 1.0 - p;
+        n += 1.0;
+    }
+    1.0 / expected
+}
+
+/// Find the pseudo-random distribution increment for a nominal chance.
+fn prd_increment(chance: f64) -> f64 {
+    if chance <= 0.0 {
+        return 0.0;
+    }
+    if chance >= 1.0 {
+        return 1.0;
+    }
+
+    // The rate grows with the increment, and the increment is never more than the chance.
+    let (mut lo, mut hi) = (0.0, chance);
+    for _ in 0..64 {
+        let mid = (lo + hi) / 2.0;
+        if prd_rate(mid) < chance {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
@@ -0,0 +1,291 @@
+//! Turn scheduling for actors with different speeds.
+
+use std::collections::BTreeMap;
+use serde;
+
+/// Speed of an actor that takes one time unit per unit of action cost.
+pub const NORMAL_SPEED: u32 = 100;
+
+/// Handle for cancelling a scheduled event.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub struct EventId {
+    time: u64,
+    seq: u64,
+}
+
+/// What happens next in a `Scheduler`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Turn<A, E> {
+    /// It's the actor's turn to act.
+    Actor(A),
+    /// A scheduled event fires.
+    Event(E),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+enum Slot<A, E> {
+    Actor(A),
+    Event(E),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct ActorState {
+    speed: u32,
+    /// Queue position of the next turn, `None` while the actor is taking its turn.
+    turn: Option<EventId>,
+    /// Leftover fraction of a time unit from earlier actions, in units of 1 / speed.
+    remainder: u64,
+}
+
+/// Discrete event scheduler for actors and timed events.
+///
+/// Time advances in integer units. An actor with `NORMAL_SPEED` spends as many time units as its
+/// action costs, a faster actor spends proportionally less time and gets more turns. Fractions
+/// of time units carry over to the actor's next action, so speeds that don't divide the costs
+/// evenly still get exactly the right number of turns in the long run.
+///
+/// Turns and events at the same time happen in the order they were scheduled, so a game replays
+/// the same way every time. The scheduler serializes with serde and can be stored in save games.
+///
+/// # Examples
+///
+/// ```
+/// use calx_alg::{Scheduler, Turn};
+///
+/// let mut scheduler = Scheduler::new();
+/// scheduler.add_actor("player", 100, 0);
+/// scheduler.add_actor("bat", 200, 0);
+/// scheduler.schedule(150, "poison");
+///
+/// let mut log = Vec::new();
+/// while let Some(turn) = scheduler.next() {
+///     if scheduler.now() > 200 {
+///         break;
+///     }
+///     match turn {
+///         Turn::Actor(a) => {
+///             log.push(a);
+///             scheduler.act(&a, 100);
+///         }
+///         Turn::Event(e) => log.push(e),
+///     }
+/// }
+/// assert_eq!(vec!["player", "bat", "bat", "player", "bat", "poison", "bat", "player", "bat"],
+///            log);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Scheduler<A: Ord, E> {
+    now: u64,
+    next_seq: u64,
+    queue: BTreeMap<EventId, Slot<A, E>>,
+    actors: BTreeMap<A, ActorState>,
+}
+
+impl<A: Ord + Clone, E> Scheduler<A, E> {
+    pub fn new() -> Scheduler<A, E> {
+        Scheduler {
+            now: 0,
+            next_seq: 0,
+            queue: BTreeMap::new(),
+            actors: BTreeMap::new(),
+        }
+    }
+
+    /// Return the current time.
+    pub fn now(&self) -> u64 { self.now }
+
+    /// Add an actor with the given speed that gets its first turn after `delay`.
+    ///
+    /// An actor that is already in the scheduler is rescheduled.
+    pub fn add_actor(&mut self, actor: A, speed: u32, delay: u64) {
+        assert!(speed > 0);
+        self.remove_actor(&actor);
+        let turn = self.push(delay, Slot::Actor(actor.clone()));
+        self.actors.insert(actor,
+                           ActorState {
+                               speed: speed,
+                               turn: Some(turn),
+                               remainder: 0,
+                           });
+    }
+
+    /// Remove an actor and its pending turn.
+    ///
+    /// Returns whether the actor was in the scheduler.
+    pub fn remove_actor(&mut self, actor: &A) -> bool {
+        match self.actors.remove(actor) {
+            Some(state) => {
+                if let Some(id) = state.turn {
+                    self.queue.remove(&id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Return the speed of an actor.
+    pub fn speed(&self, actor: &A) -> Option<u32> { self.actors.get(actor).map(|s| s.speed) }
+
+    /// Change the speed of an actor.
+    ///
+    /// The new speed applies from the actor's next action on.
+    pub fn set_speed(&mut self, actor: &A, speed: u32) {
+        assert!(speed > 0);
+        if let Some(state) = self.actors.get_mut(actor) {
+            state.speed = speed;
+            state.remainder = 0;
+        }
+    }
+
+    /// End the turn of an actor with an action of the given cost.
+    ///
+    /// The actor's next turn comes after the action's time scaled by the actor's speed. Call
+    /// this for the actor returned by `next`. An actor that isn't given a new turn stays in the
+    /// scheduler without acting until it's added again.
+    pub fn act(&mut self, actor: &A, cost: u32) {
+        let (delay, remainder) = {
+            let state = self.actors.get(actor).expect("Unknown actor");
+            assert!(state.turn.is_none(), "Actor already has a turn scheduled");
+            let total = cost as u64 * NORMAL_SPEED as u64 + state.remainder;
+            (total / state.speed as u64, total % state.speed as u64)
+        };
+        let turn = self.push(delay, Slot::Actor(actor.clone()));
+        let state = self.actors.get_mut(actor).unwrap();
+        state.turn = Some(turn);
+        state.remainder = remainder;
+    }
+
+    /// Schedule an event to happen after `delay` time units.
+    pub fn schedule(&mut self, delay: u64, event: E) -> EventId {
+        self.push(delay, Slot::Event(event))
+    }
+
+    /// Cancel a scheduled event.
+    ///
+    /// Returns the event if it hadn't happened yet.
+    pub fn cancel(&mut self, id: EventId) -> Option<E> {
+        match self.queue.get(&id) {
+            Some(&Slot::Event(_)) => {}
+            _ => return None,
+        }
+        match self.queue.remove(&id) {
+            Some(Slot::Event(e)) => Some(e),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Return the time of the next turn or event.
+    pub fn next_time(&self) -> Option<u64> { self.queue.keys().next().map(|id| id.time) }
+
+    /// Return whether no turns or events are pending.
+    pub fn is_empty(&self) -> bool { self.queue.is_empty() }
+
+    /// Advance the time to the next turn or event and return it.
+    pub fn next(&mut self) -> Option<Turn<A, E>> {
+        let id = match self.queue.keys().next() {
+            Some(&id) => id,
+            None => return None,
+        };
+        self.now = id.time;
+        match self.queue.remove(&id).unwrap() {
+            Slot::Actor(a) => {
+                self.actors.get_mut(&a).unwrap().turn = None;
+                Some(Turn::Actor(a))
+            }
+            Slot::Event(e) => Some(Turn::Event(e)),
+        }
+    }
+
+    fn push(&mut self, delay: u64, slot: Slot<A, E>) -> EventId {
+        let id = EventId {
+            time: self.now + delay,
+            seq: self.next_seq,
+        };
+        self.next_seq += 1;
+        self.queue.insert(id, slot);
+        id
+    }
+}
+
+impl<A: Ord + Clone, E> Default for Scheduler<A, E> {
+    fn default() -> Scheduler<A, E> { Scheduler::new() }
+}
+
+/// Serialization format of a scheduler.
+///
+/// Maps are saved as lists since serialization formats like JSON only allow string map keys.
+#[derive(Serialize)]
+struct SavedSchedulerRef<'a, A: 'a, E: 'a> {
+    now: u64,
+    next_seq: u64,
+    queue: Vec<(EventId, &'a Slot<A, E>)>,
+    actors: Vec<(&'a A, ActorState)>,
+}
+
+#[derive(Deserialize)]
+struct SavedScheduler<A, E> {
+    now: u64,
+    next_seq: u64,
+    queue: Vec<(EventId, Slot<A, E>)>,
+    actors: Vec<(A, ActorState)>,
+}
+
+impl<A, E> serde::Serialize for Scheduler<A, E>
+    where A: Ord + serde::Serialize,
+          E: serde::Serialize
+{
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let saved = SavedSchedulerRef {
+            now: self.now,
+            next_seq: self.next_seq,
+            queue: self.queue.iter().map(|(&id, slot)| (id, slot)).collect(),
+            actors: self.actors.iter().map(|(a, &state)| (a, state)).collect(),
+        };
+        serde::Serialize::serialize(&saved, s)
+    }
+}
+
+impl<A, E> serde::Deserialize for Scheduler<A, E>
+    where A: Ord + serde::Deserialize,
+          E: serde::Deserialize
+{
+    fn deserialize<D: serde::Deserializer>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let saved: SavedScheduler<A, E> = serde::Deserialize::deserialize(d)?;
+        let (n_queue, n_actors) = (saved.queue.len(), saved.actors.len());
+        let ret = Scheduler {
+            now: saved.now,
+            next_seq: saved.next_seq,
+            queue: saved.queue.into_iter().collect(),
+            actors: saved.actors.into_iter().collect(),
+        };
+
+        if ret.queue.len() != n_queue || ret.actors.len() != n_actors {
+            return Err(D::Error::custom("duplicate entries in scheduler"));
+        }
+        for (id, slot) in &ret.queue {
+            if id.seq >= ret.next_seq || id.time < ret.now {
+                return Err(D::Error::custom("bad event id in scheduler"));
+            }
+            if let Slot::Actor(ref a) = *slot {
+                if ret.actors.get(a).map_or(true, |state| state.turn != Some(*id)) {
+                    return Err(D::Error::custom("actor turn not matching scheduler queue"));
+                }
+            }
+        }
+        for state in ret.actors.values() {
+            if state.speed == 0 {
+                return Err(D::Error::custom("zero actor speed in scheduler"));
+            }
+            if let Some(id) = state.turn {
+                if !ret.queue.contains_key(&id) {
+                    return Err(D::Error::custom("actor turn not matching scheduler queue"));
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+}
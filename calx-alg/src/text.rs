@@ -1,5 +1,9 @@
 //! String processing utilities
 
+use std::error;
+use std::fmt;
+use calx_color::SRgba;
+
 /// Split a long line into multiple lines that fit a given width.
 ///
 /// Will treat newlines in the input as regular whitespace, you probably want to split your input
@@ -102,3 +106,148 @@ impl<'a, F> Iterator for LineSplit<'a, F>
         Some(ret)
     }
 }
+
+/// A piece of text with a single style.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TextSpan {
+    pub text: String,
+    /// Color of the text, `None` for the default color.
+    pub color: Option<SRgba>,
+}
+
+impl TextSpan {
+    pub fn new<S: Into<String>>(text: S, color: Option<SRgba>) -> TextSpan {
+        TextSpan {
+            text: text.into(),
+            color: color,
+        }
+    }
+}
+
+/// Error from parsing text markup.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MarkupError {
+    /// The tag is not a known color name or hex color.
+    UnknownColor(String),
+    /// A `{` without a matching `}`.
+    UnclosedTag,
+    /// A `{/}` with no open style to close.
+    UnmatchedClose,
+}
+
+impl fmt::Display for MarkupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MarkupError::UnknownColor(ref c) => write!(f, "Unknown markup color {:?}", c),
+            MarkupError::UnclosedTag => write!(f, "Markup tag without closing brace"),
+            MarkupError::UnmatchedClose => write!(f, "Markup style closed without being opened"),
+        }
+    }
+}
+
+impl error::Error for MarkupError {
+    fn description(&self) -> &str {
+        match *self {
+            MarkupError::UnknownColor(_) => "unknown markup color",
+            MarkupError::UnclosedTag => "unclosed markup tag",
+            MarkupError::UnmatchedClose => "unmatched markup close tag",
+        }
+    }
+}
+
+/// Parse text with color markup into styled spans.
+///
+/// `{color}` starts text in the color, which can be any color name or hex value that `SRgba`
+/// parses. `{/}` goes back to the color before the last `{color}` tag. Styles still open at the
+/// end of the text are closed implicitly. Write `{{` for a literal `{`.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate calx_alg;
+/// # extern crate calx_color;
+/// # fn main() {
+/// use calx_alg::{TextSpan, parse_markup};
+/// use calx_color::SRgba;
+///
+/// let red = Some(SRgba::new(0xff, 0, 0, 0xff));
+/// assert_eq!(parse_markup("{red}Danger{/} ahead").unwrap(),
+///            vec![TextSpan::new("Danger", red), TextSpan::new(" ahead", None)]);
+/// # }
+/// ```
+pub fn parse_markup(text: &str) -> Result<Vec<TextSpan>, MarkupError> {
+    let mut ret: Vec<TextSpan> = Vec::new();
+    let mut styles: Vec<Option<SRgba>> = vec![None];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '{' && chars.peek() != Some(&'{') {
+            let mut tag = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => tag.push(c),
+                    None => return Err(MarkupError::UnclosedTag),
+                }
+            }
+
+            if tag == "/" {
+                if styles.len() == 1 {
+                    return Err(MarkupError::UnmatchedClose);
+                }
+                styles.pop();
+            } else {
+                match tag.parse::<SRgba>() {
+                    Ok(color) => styles.push(Some(color)),
+                    Err(_) => return Err(MarkupError::UnknownColor(tag)),
+                }
+            }
+            continue;
+        }
+        if c == '{' {
+            // Escaped brace.
+            chars.next();
+        }
+
+        let color = styles[styles.len() - 1];
+        if ret.last().map_or(true, |s| s.color != color) {
+            ret.push(TextSpan::new(String::new(), color));
+        }
+        let n = ret.len();
+        ret[n - 1].text.push(c);
+    }
+
+    Ok(ret)
+}
+
+/// Split styled text into lines that fit a given width.
+///
+/// Works like `split_line` on the concatenated text of the spans, and returns each line as a
+/// list of spans that keep the styles of the text they came from. A span that continues over a
+/// line break is split into a span on each line.
+pub fn split_spans<F>(spans: &[TextSpan], char_width: F, max_width: f32) -> Vec<Vec<TextSpan>>
+    where F: Fn(char) -> f32
+{
+    let text: String = spans.iter().map(|s| &s.text[..]).collect();
+
+    // Byte ranges of the spans in the text.
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+    for s in spans {
+        ranges.push((pos, pos + s.text.len(), s.color));
+        pos += s.text.len();
+    }
+
+    split_line(&text, char_width, max_width)
+        .map(|line| {
+            let start = line.as_ptr() as usize - text.as_ptr() as usize;
+            let end = start + line.len();
+            ranges.iter()
+                  .filter(|&&(a, b, _)| a < end && b > start)
+                  .map(|&(a, b, color)| {
+                      TextSpan::new(&text[a.max(start)..b.min(end)], color)
+                  })
+                  .collect()
+        })
+        .collect()
+}
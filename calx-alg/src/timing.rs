@@ -1,25 +1,184 @@
 //! Time-related utilities
 
+use std::cell::Cell;
+use std::rc::Rc;
 use time;
 use std::thread;
 use std::time::Duration;
 
+/// A source of time for the timing utilities.
+///
+/// The utilities read the real system clock by default. Giving them a different clock lets tests
+/// control time exactly and lets games pause or slow down time.
+pub trait Clock {
+    /// Return the current time in seconds.
+    fn now(&self) -> f64;
+
+    /// Wait until the given number of seconds has passed on this clock.
+    fn sleep(&self, seconds: f64);
+}
+
+impl<'a, C: Clock> Clock for &'a C {
+    fn now(&self) -> f64 { (**self).now() }
+
+    fn sleep(&self, seconds: f64) { (**self).sleep(seconds) }
+}
+
+impl<C: Clock> Clock for Rc<C> {
+    fn now(&self) -> f64 { (**self).now() }
+
+    fn sleep(&self, seconds: f64) { (**self).sleep(seconds) }
+}
+
+/// The system clock.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    fn now(&self) -> f64 { time::precise_time_s() }
+
+    fn sleep(&self, seconds: f64) {
+        if seconds > 0.0 {
+            thread::sleep(Duration::from_millis((seconds * 1e3) as u64));
+        }
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Sleeping advances the clock immediately. Share the clock between the timing utilities and the
+/// code driving it with a reference or an `Rc`.
+#[derive(Clone, Default, Debug)]
+pub struct ManualClock {
+    time: Cell<f64>,
+}
+
+impl ManualClock {
+    pub fn new(time: f64) -> ManualClock { ManualClock { time: Cell::new(time) } }
+
+    /// Move the clock forward.
+    pub fn advance(&self, seconds: f64) { self.time.set(self.time.get() + seconds); }
+
+    /// Set the clock to the given time.
+    pub fn set(&self, time: f64) { self.time.set(time); }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 { self.time.get() }
+
+    fn sleep(&self, seconds: f64) {
+        if seconds > 0.0 {
+            self.advance(seconds);
+        }
+    }
+}
+
+/// A clock that runs at a multiple of the speed of another clock and can be paused.
+///
+/// Changing the speed doesn't make the time jump, the clock continues from its current time at
+/// the new speed.
+#[derive(Clone, Debug)]
+pub struct ScaledClock<C> {
+    source: C,
+    scale: Cell<f64>,
+    paused: Cell<bool>,
+    /// Source time and own time when the speed last changed.
+    base: Cell<(f64, f64)>,
+}
+
+impl<C: Clock> ScaledClock<C> {
+    /// Create a clock that starts at the current time of the source clock at normal speed.
+    pub fn new(source: C) -> ScaledClock<C> {
+        let now = source.now();
+        ScaledClock {
+            source: source,
+            scale: Cell::new(1.0),
+            paused: Cell::new(false),
+            base: Cell::new((now, now)),
+        }
+    }
+
+    /// Return the speed relative to the source clock.
+    pub fn scale(&self) -> f64 { self.scale.get() }
+
+    /// Set the speed relative to the source clock.
+    pub fn set_scale(&self, scale: f64) {
+        assert!(scale >= 0.0);
+        self.rebase();
+        self.scale.set(scale);
+    }
+
+    /// Stop the clock.
+    pub fn pause(&self) {
+        self.rebase();
+        self.paused.set(true);
+    }
+
+    /// Start a paused clock again.
+    pub fn resume(&self) {
+        self.rebase();
+        self.paused.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool { self.paused.get() }
+
+    fn speed(&self) -> f64 { if self.paused.get() { 0.0 } else { self.scale.get() } }
+
+    fn rebase(&self) { self.base.set((self.source.now(), self.now())); }
+}
+
+impl<C: Clock> Clock for ScaledClock<C> {
+    fn now(&self) -> f64 {
+        let (source_base, base) = self.base.get();
+        base + (self.source.now() - source_base) * self.speed()
+    }
+
+    /// Sleep in source clock time that corresponds to the scaled time.
+    ///
+    /// A stopped clock can't wait for time to pass, so sleeping on a paused or zero speed clock
+    /// returns immediately.
+    fn sleep(&self, seconds: f64) {
+        let speed = self.speed();
+        if speed > 0.0 {
+            self.source.sleep(seconds / speed);
+        }
+    }
+}
+
 /// Animation cycle based on system clock.
 pub fn cycle_anim<'a, T>(period_s: f64, frames: &'a [T]) -> &'a T {
+    cycle_anim_with(&RealClock, period_s, frames)
+}
+
+/// Animation cycle based on the given clock.
+pub fn cycle_anim_with<'a, C: Clock, T>(clock: &C, period_s: f64, frames: &'a [T]) -> &'a T {
     assert!(period_s > 0.0);
     assert!(frames.len() > 0);
-    let idx = (time::precise_time_s() / period_s) as usize % frames.len();
+    let idx = (clock.now() / period_s) as usize % frames.len();
 
     &frames[idx]
 }
 
 /// Time-plot that spikes at given intervals for the given time.
-pub fn spike(down_s: f64, up_s: f64) -> bool { time::precise_time_s() % (down_s + up_s) > down_s }
+pub fn spike(down_s: f64, up_s: f64) -> bool { spike_with(&RealClock, down_s, up_s) }
+
+/// Time-plot on the given clock that spikes at given intervals for the given time.
+pub fn spike_with<C: Clock>(clock: &C, down_s: f64, up_s: f64) -> bool {
+    clock.now() % (down_s + up_s) > down_s
+}
 
 pub fn single_anim<'a, T>(start_s: f64, period_s: f64, frames: &'a [T]) -> &'a T {
+    single_anim_with(&RealClock, start_s, period_s, frames)
+}
+
+pub fn single_anim_with<'a, C: Clock, T>(clock: &C,
+                                         start_s: f64,
+                                         period_s: f64,
+                                         frames: &'a [T])
+                                         -> &'a T {
     assert!(period_s > 0.0);
     assert!(frames.len() > 0);
-    let mut idx = ((time::precise_time_s() - start_s) / period_s) as i32;
+    let mut idx = ((clock.now() - start_s) / period_s) as i32;
     if idx < 0 {
         idx = 0;
     }
@@ -31,21 +190,28 @@ pub fn single_anim<'a, T>(start_s: f64, period_s: f64, frames: &'a [T]) -> &'a T
 }
 
 #[derive(Copy, Clone)]
-pub struct Ticker {
+pub struct Ticker<C = RealClock> {
+    clock: C,
     period_s: f64,
     last_t: f64,
 }
 
 impl Ticker {
-    pub fn new(period_s: f64) -> Ticker {
+    pub fn new(period_s: f64) -> Ticker { Ticker::with_clock(RealClock, period_s) }
+}
+
+impl<C: Clock> Ticker<C> {
+    pub fn with_clock(clock: C, period_s: f64) -> Ticker<C> {
+        let last_t = clock.now();
         Ticker {
+            clock: clock,
             period_s: period_s,
-            last_t: time::precise_time_s(),
+            last_t: last_t,
         }
     }
 
     fn time_remaining(&mut self) -> Option<f64> {
-        let now = time::precise_time_s();
+        let now = self.clock.now();
         if now - self.last_t > self.period_s {
             if now - self.last_t > self.period_s * 2.0 {
                 // Bring the clock up to speed if running very late.
@@ -64,7 +230,7 @@ impl Ticker {
     pub fn wait_for_tick(&mut self) {
         match self.time_remaining() {
             Some(t) => {
-                thread::sleep(Duration::from_millis((t * 1e3) as u64));
+                self.clock.sleep(t);
                 self.last_t += self.period_s;
             }
             _ => {}
@@ -73,7 +239,8 @@ impl Ticker {
 }
 
 #[derive(Copy, Clone)]
-pub struct TimePerFrame {
+pub struct TimePerFrame<C = RealClock> {
+    clock: C,
     update_weight: f64,
     start_t: f64,
     pub average: f64,
@@ -82,25 +249,34 @@ pub struct TimePerFrame {
 
 impl TimePerFrame {
     pub fn new(update_weight: f64) -> TimePerFrame {
+        TimePerFrame::with_clock(RealClock, update_weight)
+    }
+}
+
+impl<C: Clock> TimePerFrame<C> {
+    pub fn with_clock(clock: C, update_weight: f64) -> TimePerFrame<C> {
         assert!(update_weight >= 0.0 && update_weight <= 1.0);
+        let start_t = clock.now();
         TimePerFrame {
+            clock: clock,
             update_weight: update_weight,
-            start_t: time::precise_time_s(),
+            start_t: start_t,
             average: 0.0,
             last: 0.0,
         }
     }
 
-    pub fn begin(&mut self) { self.start_t = time::precise_time_s(); }
+    pub fn begin(&mut self) { self.start_t = self.clock.now(); }
 
     pub fn end(&mut self) {
-        self.last = time::precise_time_s() - self.start_t;
+        self.last = self.clock.now() - self.start_t;
         self.average = self.update_weight * self.last + (1.0 - self.update_weight) * self.average;
     }
 }
 
 /// Exponential moving average duration.
-pub struct AverageDuration {
+pub struct AverageDuration<C = RealClock> {
+    clock: C,
     weight: f64,
     last_time: f64,
     pub value: f64,
@@ -112,17 +288,143 @@ impl AverageDuration {
     /// indicates how fast the older values should decay. Weight 1.0 causes
     /// old values to decay immediately.
     pub fn new(init: f64, weight: f64) -> AverageDuration {
+        AverageDuration::with_clock(RealClock, init, weight)
+    }
+}
+
+impl<C: Clock> AverageDuration<C> {
+    /// Create an average duration that reads the given clock.
+    pub fn with_clock(clock: C, init: f64, weight: f64) -> AverageDuration<C> {
         assert!(weight > 0.0 && weight <= 1.0);
+        let last_time = clock.now();
         AverageDuration {
+            clock: clock,
             weight: weight,
-            last_time: time::precise_time_s(),
+            last_time: last_time,
             value: init,
         }
     }
 
     pub fn tick(&mut self) {
-        let t = time::precise_time_s();
+        let t = self.clock.now();
         self.value = self.weight * (t - self.last_time) + (1.0 - self.weight) * self.value;
         self.last_time = t;
     }
 }
+
+/// Fixed timestep driver for a game loop.
+///
+/// The game state is updated in steps of constant length, regardless of the frame rate. Every
+/// frame, `advance` tells how many updates the time since the previous frame covers. Time left
+/// over after the updates carries on to the next frame, and `alpha` tells how far the frame is
+/// between the last update and the next one, for interpolating the rendered state.
+///
+/// If the updates fall behind more than `max_steps` per frame, the extra updates are dropped to
+/// let the loop recover instead of trying to catch up forever.
+///
+/// # Examples
+///
+/// ```no_run
+/// use calx_alg::timing::FixedTimestep;
+///
+/// let mut timestep = FixedTimestep::new(1.0 / 60.0, 5);
+/// loop {
+///     for _ in 0..timestep.advance() {
+///         // Update the game state by timestep.step() seconds.
+///     }
+///     let alpha = timestep.alpha();
+///     // Render the state interpolated by alpha between the last two updates.
+/// #   break;
+/// }
+/// ```
+#[derive(Copy, Clone)]
+pub struct FixedTimestep<C = RealClock> {
+    clock: C,
+    step_s: f64,
+    max_steps: u32,
+    last_t: f64,
+    accumulator: f64,
+    stats: TimestepStats,
+}
+
+/// Statistics of a `FixedTimestep`.
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct TimestepStats {
+    /// Number of frames advanced.
+    pub frames: u64,
+    /// Number of updates run.
+    pub updates: u64,
+    /// Number of updates skipped because of falling behind.
+    pub dropped: u64,
+}
+
+impl FixedTimestep {
+    /// Create a driver that updates every `step_s` seconds and at most `max_steps` times per
+    /// frame.
+    pub fn new(step_s: f64, max_steps: u32) -> FixedTimestep {
+        FixedTimestep::with_clock(RealClock, step_s, max_steps)
+    }
+}
+
+impl<C: Clock> FixedTimestep<C> {
+    /// Create a driver that reads the given clock.
+    pub fn with_clock(clock: C, step_s: f64, max_steps: u32) -> FixedTimestep<C> {
+        assert!(step_s > 0.0);
+        assert!(max_steps > 0);
+        let last_t = clock.now();
+        FixedTimestep {
+            clock: clock,
+            step_s: step_s,
+            max_steps: max_steps,
+            last_t: last_t,
+            accumulator: 0.0,
+            stats: TimestepStats::default(),
+        }
+    }
+
+    /// Start a new frame and return the number of updates to run.
+    pub fn advance(&mut self) -> u32 {
+        let now = self.clock.now();
+        // Don't go back in time if the clock is reset.
+        self.accumulator += (now - self.last_t).max(0.0);
+        self.last_t = now;
+
+        let due = (self.accumulator / self.step_s).floor() as u64;
+        let steps = if due > self.max_steps as u64 {
+            self.stats.dropped += due - self.max_steps as u64;
+            self.max_steps
+        } else {
+            due as u32
+        };
+        self.accumulator -= due as f64 * self.step_s;
+
+        self.stats.frames += 1;
+        self.stats.updates += steps as u64;
+        steps
+    }
+
+    /// Return how far the current frame is from the last update towards the next one, in [0, 1).
+    pub fn alpha(&self) -> f64 { (self.accumulator / self.step_s).min(1.0) }
+
+    /// Return the length of an update step in seconds.
+    pub fn step(&self) -> f64 { self.step_s }
+
+    /// Return the update statistics.
+    pub fn stats(&self) -> TimestepStats { self.stats }
+
+    /// Return the time in seconds until the next update is due.
+    pub fn time_to_update(&self) -> f64 {
+        (self.step_s - self.accumulator - (self.clock.now() - self.last_t)).max(0.0)
+    }
+
+    /// Sleep until the next update is due.
+    pub fn wait_for_update(&self) { self.clock.sleep(self.time_to_update()); }
+
+    /// Forget the time accumulated so far.
+    ///
+    /// Use after a long pause, like loading a level, that shouldn't be caught up with.
+    pub fn reset(&mut self) {
+        self.last_t = self.clock.now();
+        self.accumulator = 0.0;
+    }
+}
@@ -0,0 +1,381 @@
+//! Animating values over time.
+
+use std::ops::{Add, Mul, Sub};
+use ease::Easing;
+use lerp;
+
+/// How an animation repeats.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Playback {
+    /// Play from start to end.
+    Once,
+    /// Jump back to the start after reaching the end.
+    Loop,
+    /// Play backwards to the start after reaching the end.
+    PingPong,
+}
+
+/// Something that happened during an animation update.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TweenEvent {
+    /// The delay ran out and the animation started moving.
+    Started,
+    /// The animation completed a cycle and started a new one.
+    ///
+    /// An endless animation reports this once per update even if the update covers several
+    /// cycles.
+    Looped,
+    /// The animation completed its last cycle.
+    Finished,
+}
+
+/// Time keeping shared by the animation types.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+struct Playhead {
+    duration: f32,
+    delay: f32,
+    playback: Playback,
+    /// Number of cycles to play, `None` for forever.
+    cycles: Option<u32>,
+    /// Time since the animation was started, including the delay.
+    elapsed: f32,
+    finished: bool,
+}
+
+impl Playhead {
+    fn new(duration: f32) -> Playhead {
+        assert!(duration >= 0.0);
+        Playhead {
+            duration: duration,
+            delay: 0.0,
+            playback: Playback::Once,
+            cycles: Some(1),
+            elapsed: 0.0,
+            finished: false,
+        }
+    }
+
+    /// Length of one cycle, a ping-pong cycle goes there and back.
+    fn cycle_length(&self) -> f32 {
+        match self.playback {
+            Playback::PingPong => self.duration * 2.0,
+            _ => self.duration,
+        }
+    }
+
+    fn update(&mut self, dt: f32) -> Vec<TweenEvent> {
+        let mut ret = Vec::new();
+        if self.finished {
+            return ret;
+        }
+
+        let prev = self.elapsed - self.delay;
+        self.elapsed += dt;
+        let now = self.elapsed - self.delay;
+        if prev <= 0.0 && now > 0.0 || prev < 0.0 && now >= 0.0 {
+            ret.push(TweenEvent::Started);
+        }
+        if now < 0.0 {
+            return ret;
+        }
+
+        let length = self.cycle_length();
+        if length <= 0.0 {
+            self.finished = true;
+            ret.push(TweenEvent::Finished);
+            return ret;
+        }
+
+        let prev_cycle = (prev.max(0.0) / length).floor() as u64;
+        let cycle = (now / length).floor() as u64;
+        match self.cycles {
+            None => {
+                // An endless animation can cross any number of cycles in one long update,
+                // report them as one loop.
+                if cycle > prev_cycle {
+                    ret.push(TweenEvent::Looped);
+                }
+
+                // Drop the completed cycles so that the time doesn't grow until it loses
+                // precision. Keep the time above zero so the start isn't reported again.
+                if now > length {
+                    let phase = now % length;
+                    self.elapsed = self.delay + if phase > 0.0 { phase } else { length };
+                }
+            }
+            Some(n) => {
+                // Report every cycle boundary crossed during the update.
+                for c in (prev_cycle + 1)..(cycle + 1) {
+                    if c >= n as u64 {
+                        self.finished = true;
+                        self.elapsed = self.delay + c as f32 * length;
+                        ret.push(TweenEvent::Finished);
+                        break;
+                    }
+                    ret.push(TweenEvent::Looped);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Position of the animation between the start and the end.
+    fn progress(&self) -> f32 {
+        if self.finished {
+            return match self.playback {
+                Playback::PingPong => 0.0,
+                _ => 1.0,
+            };
+        }
+
+        let now = self.elapsed - self.delay;
+        if now <= 0.0 || self.duration <= 0.0 {
+            return 0.0;
+        }
+        let phase = now % self.cycle_length();
+        if phase < self.duration {
+            phase / self.duration
+        } else {
+            2.0 - phase / self.duration
+        }
+    }
+
+    fn repeat(&mut self, cycles: u32) {
+        if self.playback == Playback::Once {
+            self.playback = Playback::Loop;
+        }
+        self.cycles = Some(cycles);
+    }
+
+    fn reset(&mut self) {
+        self.elapsed = 0.0;
+        self.finished = false;
+    }
+}
+
+/// An animation between two values.
+///
+/// The tween is driven by calling `update` with the time elapsed since the last update, so it
+/// behaves the same whether the time comes from a real clock or a test.
+///
+/// # Examples
+///
+/// ```
+/// use calx_alg::{Tween, TweenEvent};
+/// use calx_alg::ease::Easing;
+///
+/// let mut fade = Tween::new(0.0f32, 1.0, 2.0).easing(Easing::QuadraticIn).delay(0.5);
+/// assert_eq!(vec![TweenEvent::Started], fade.update(1.5));
+/// assert_eq!(0.25, fade.value());
+/// assert_eq!(vec![TweenEvent::Finished], fade.update(1.5));
+/// assert_eq!(1.0, fade.value());
+/// ```
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Tween<T> {
+    from: T,
+    to: T,
+    easing: Easing,
+    playhead: Playhead,
+}
+
+impl<T> Tween<T> {
+    /// Create a tween that plays once from `from` to `to` in `duration` time units.
+    pub fn new(from: T, to: T, duration: f32) -> Tween<T> {
+        Tween {
+            from: from,
+            to: to,
+            easing: Easing::Linear,
+            playhead: Playhead::new(duration),
+        }
+    }
+
+    /// Set the easing curve.
+    pub fn easing(mut self, easing: Easing) -> Tween<T> {
+        self.easing = easing;
+        self
+    }
+
+    /// Wait before starting the animation.
+    pub fn delay(mut self, delay: f32) -> Tween<T> {
+        self.playhead.delay = delay;
+        self
+    }
+
+    /// Loop the animation forever.
+    pub fn looping(mut self) -> Tween<T> {
+        self.playhead.playback = Playback::Loop;
+        self.playhead.cycles = None;
+        self
+    }
+
+    /// Play the animation back and forth forever.
+    pub fn ping_pong(mut self) -> Tween<T> {
+        self.playhead.playback = Playback::PingPong;
+        self.playhead.cycles = None;
+        self
+    }
+
+    /// Play the animation the given number of cycles and stop.
+    ///
+    /// An animation that plays once is made to loop.
+    pub fn repeat(mut self, cycles: u32) -> Tween<T> {
+        self.playhead.repeat(cycles);
+        self
+    }
+
+    /// Advance the animation by `dt` time units and return what happened.
+    pub fn update(&mut self, dt: f32) -> Vec<TweenEvent> { self.playhead.update(dt) }
+
+    /// Return the eased position of the animation, from 0 at the start to 1 at the end.
+    pub fn progress(&self) -> f32 { self.easing.apply(self.playhead.progress()) }
+
+    /// Return whether the animation has played all its cycles.
+    pub fn is_finished(&self) -> bool { self.playhead.finished }
+
+    /// Rewind the animation to the start, including the delay.
+    pub fn reset(&mut self) { self.playhead.reset(); }
+}
+
+impl<T> Tween<T>
+    where T: Add<T, Output = T> + Sub<T, Output = T> + Mul<f32, Output = T> + Copy
+{
+    /// Return the current value of the animation.
+    pub fn value(&self) -> T { lerp(self.from, self.to, self.progress()) }
+}
+
+/// A keyframe in a `KeyframeTrack`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    /// Time of the keyframe from the start of the track.
+    pub time: f32,
+    pub value: T,
+    /// Easing of the segment that ends at this keyframe.
+    pub easing: Easing,
+}
+
+/// An animation through a sequence of keyframes.
+///
+/// # Examples
+///
+/// ```
+/// use calx_alg::KeyframeTrack;
+/// use calx_alg::ease::Easing;
+///
+/// let mut bob = KeyframeTrack::new(0.0f32)
+///                   .then(1.0, 10.0, Easing::Linear)
+///                   .hold(1.0)
+///                   .then(2.0, 0.0, Easing::CubicOut)
+///                   .looping();
+/// assert_eq!(4.0, bob.duration());
+/// bob.update(1.5);
+/// assert_eq!(10.0, bob.value());
+/// bob.update(3.0);
+/// assert_eq!(5.0, bob.value());
+/// ```
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeyframeTrack<T> {
+    keys: Vec<Keyframe<T>>,
+    playhead: Playhead,
+}
+
+impl<T> KeyframeTrack<T> {
+    /// Create a track that starts from the given value.
+    pub fn new(start: T) -> KeyframeTrack<T> {
+        KeyframeTrack {
+            keys: vec![Keyframe {
+                           time: 0.0,
+                           value: start,
+                           easing: Easing::Linear,
+                       }],
+            playhead: Playhead::new(0.0),
+        }
+    }
+
+    /// Add a keyframe `duration` time units after the previous one.
+    pub fn then(mut self, duration: f32, value: T, easing: Easing) -> KeyframeTrack<T> {
+        assert!(duration >= 0.0);
+        let time = self.duration() + duration;
+        self.keys.push(Keyframe {
+            time: time,
+            value: value,
+            easing: easing,
+        });
+        self.playhead.duration = time;
+        self
+    }
+
+    /// Stay at the previous keyframe value for `duration` time units.
+    pub fn hold(self, duration: f32) -> KeyframeTrack<T>
+        where T: Clone
+    {
+        let value = self.keys[self.keys.len() - 1].value.clone();
+        self.then(duration, value, Easing::Linear)
+    }
+
+    /// Wait before starting the animation.
+    pub fn delay(mut self, delay: f32) -> KeyframeTrack<T> {
+        self.playhead.delay = delay;
+        self
+    }
+
+    /// Loop the animation forever.
+    pub fn looping(mut self) -> KeyframeTrack<T> {
+        self.playhead.playback = Playback::Loop;
+        self.playhead.cycles = None;
+        self
+    }
+
+    /// Play the animation back and forth forever.
+    pub fn ping_pong(mut self) -> KeyframeTrack<T> {
+        self.playhead.playback = Playback::PingPong;
+        self.playhead.cycles = None;
+        self
+    }
+
+    /// Play the animation the given number of cycles and stop.
+    ///
+    /// An animation that plays once is made to loop.
+    pub fn repeat(mut self, cycles: u32) -> KeyframeTrack<T> {
+        self.playhead.repeat(cycles);
+        self
+    }
+
+    /// Return the time of the last keyframe.
+    pub fn duration(&self) -> f32 { self.keys[self.keys.len() - 1].time }
+
+    /// Return the keyframes of the track.
+    pub fn keys(&self) -> &[Keyframe<T>] { &self.keys }
+
+    /// Advance the animation by `dt` time units and return what happened.
+    pub fn update(&mut self, dt: f32) -> Vec<TweenEvent> { self.playhead.update(dt) }
+
+    /// Return the current time on the track.
+    pub fn time(&self) -> f32 { self.playhead.progress() * self.duration() }
+
+    /// Return whether the animation has played all its cycles.
+    pub fn is_finished(&self) -> bool { self.playhead.finished }
+
+    /// Rewind the animation to the start, including the delay.
+    pub fn reset(&mut self) { self.playhead.reset(); }
+}
+
+impl<T> KeyframeTrack<T>
+    where T: Add<T, Output = T> + Sub<T, Output = T> + Mul<f32, Output = T> + Copy
+{
+    /// Return the current value of the animation.
+    pub fn value(&self) -> T { self.sample(self.time()) }
+
+    /// Return the value of the track at the given time.
+    pub fn sample(&self, time: f32) -> T {
+        // Index of the first keyframe after the time.
+        let i = match self.keys.iter().position(|k| k.time > time) {
+            Some(0) => return self.keys[0].value,
+            Some(i) => i,
+            None => return self.keys[self.keys.len() - 1].value,
+        };
+
+        let (a, b) = (&self.keys[i - 1], &self.keys[i]);
+        let t = (time - a.time) / (b.time - a.time);
+        lerp(a.value, b.value, b.easing.apply(t))
+    }
+}
@@ -0,0 +1,234 @@
+//! Weighted random tables.
+
+use std::iter::FromIterator;
+use rand::Rng;
+use serde;
+
+/// An entry in a weighted table.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum TableEntry<T> {
+    /// A single item.
+    Item(T),
+    /// A sub-table that is sampled again when the entry is chosen.
+    Table(WeightedTable<T>),
+}
+
+/// A table of weighted items for fast repeated sampling.
+///
+/// Sampling uses Walker's alias method and takes constant time regardless of the size of the
+/// table. Changing the table rebuilds the alias tables in linear time.
+///
+/// The table serializes as a list of `(weight, entry)` pairs, the alias tables are rebuilt when
+/// deserializing.
+///
+/// # Examples
+///
+/// ```
+/// # extern crate rand;
+/// # extern crate calx_alg;
+/// # fn main() {
+/// use calx_alg::WeightedTable;
+///
+/// let mut gems = WeightedTable::new();
+/// gems.add(1.0, "ruby");
+/// gems.add(1.0, "emerald");
+///
+/// let mut loot: WeightedTable<&str> = vec![(5.0, "gold"), (3.0, "potion")].into_iter().collect();
+/// loot.add_table(2.0, gems);
+///
+/// let mut rng = rand::thread_rng();
+/// assert!(loot.sample(&mut rng).is_some());
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct WeightedTable<T> {
+    entries: Vec<(f32, TableEntry<T>)>,
+    /// Probability of picking the entry itself instead of its alias.
+    prob: Vec<f64>,
+    alias: Vec<usize>,
+}
+
+impl<T> WeightedTable<T> {
+    /// Create an empty table.
+    pub fn new() -> WeightedTable<T> {
+        WeightedTable {
+            entries: Vec::new(),
+            prob: Vec::new(),
+            alias: Vec::new(),
+        }
+    }
+
+    /// Add an item to the table.
+    pub fn add(&mut self, weight: f32, item: T) { self.push(weight, TableEntry::Item(item)); }
+
+    /// Add a sub-table to the table.
+    ///
+    /// When the sub-table is chosen, the result is sampled from the sub-table.
+    pub fn add_table(&mut self, weight: f32, table: WeightedTable<T>) {
+        self.push(weight, TableEntry::Table(table));
+    }
+
+    /// Add an entry to the table.
+    ///
+    /// Panics if the weight is negative or not finite.
+    pub fn push(&mut self, weight: f32, entry: TableEntry<T>) {
+        assert!(valid_weight(weight), "Bad weight {}", weight);
+        self.entries.push((weight, entry));
+        self.build();
+    }
+
+    /// Return the entries with their weights.
+    pub fn entries(&self) -> &[(f32, TableEntry<T>)] { &self.entries }
+
+    /// Return the sum of the entry weights.
+    pub fn total_weight(&self) -> f32 { self.entries.iter().map(|&(w, _)| w).sum() }
+
+    /// Return the number of entries in the table.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Return whether the table has no entries.
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// Choose an item with probability weighted by the entry weights.
+    ///
+    /// Returns `None` if the table has no entries with positive weight or if the chosen
+    /// sub-table is empty.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Option<&T> {
+        match self.pick(rng) {
+            Some(i) => {
+                match self.entries[i].1 {
+                    TableEntry::Item(ref item) => Some(item),
+                    TableEntry::Table(ref table) => table.sample(rng),
+                }
+            }
+            None => None,
+        }
+    }
+
+    /// Choose an item and remove it from the table.
+    ///
+    /// This samples without replacement, every item is drawn at most once. An item drawn from a
+    /// sub-table is removed from the sub-table, and sub-tables that run out of items with
+    /// positive weight are removed from the table. Returns `None` once no items with positive
+    /// weight are left.
+    pub fn take<R: Rng>(&mut self, rng: &mut R) -> Option<T> {
+        loop {
+            let i = match self.pick(rng) {
+                Some(i) => i,
+                None => return None,
+            };
+
+            // Draw from a sub-table, and only remove the sub-table once it can't be drawn from.
+            let drawn = match self.entries[i].1 {
+                TableEntry::Item(_) => None,
+                TableEntry::Table(ref mut table) => {
+                    let drawn = table.take(rng);
+                    if drawn.is_some() && !table.prob.is_empty() {
+                        return drawn;
+                    }
+                    drawn
+                }
+            };
+
+            let (_, entry) = self.entries.remove(i);
+            self.build();
+            match entry {
+                TableEntry::Item(item) => return Some(item),
+                // A sub-table without positive weights yields nothing, try another entry.
+                TableEntry::Table(_) => {
+                    if drawn.is_some() {
+                        return drawn;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Return the index of a random entry.
+    fn pick<R: Rng>(&self, rng: &mut R) -> Option<usize> {
+        if self.prob.is_empty() {
+            return None;
+        }
+        let i = rng.gen_range(0, self.prob.len());
+        if rng.gen::<f64>() < self.prob[i] {
+            Some(i)
+        } else {
+            Some(self.alias[i])
+        }
+    }
+
+    /// Build the alias tables with Vose's algorithm.
+    fn build(&mut self) {
+        self.prob.clear();
+        self.alias.clear();
+
+        let n = self.entries.len();
+        let total: f64 = self.entries.iter().map(|&(w, _)| w as f64).sum();
+        if total <= 0.0 {
+            return;
+        }
+
+        // Scale the probabilities so that the average is one.
+        let mut scaled: Vec<f64> = self.entries
+                                       .iter()
+                                       .map(|&(w, _)| w as f64 * n as f64 / total)
+                                       .collect();
+        self.prob = vec![1.0; n];
+        self.alias = (0..n).collect();
+
+        let mut small: Vec<usize> = (0..n).filter(|&i| scaled[i] < 1.0).collect();
+        let mut large: Vec<usize> = (0..n).filter(|&i| scaled[i] >= 1.0).collect();
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            self.prob[s] = scaled[s];
+            self.alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // Whatever remains has probability one up to rounding errors.
+    }
+}
+
+/// Weights must be finite and not negative, infinite weights break the probabilities.
+fn valid_weight(weight: f32) -> bool { weight >= 0.0 && weight.is_finite() }
+
+impl<T> Default for WeightedTable<T> {
+    fn default() -> WeightedTable<T> { WeightedTable::new() }
+}
+
+impl<T> FromIterator<(f32, T)> for WeightedTable<T> {
+    fn from_iter<I: IntoIterator<Item = (f32, T)>>(iter: I) -> WeightedTable<T> {
+        let mut ret = WeightedTable::new();
+        for (weight, item) in iter {
+            assert!(valid_weight(weight), "Bad weight {}", weight);
+            ret.entries.push((weight, TableEntry::Item(item)));
+        }
+        ret.build();
+        ret
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for WeightedTable<T> {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&self.entries, s)
+    }
+}
+
+impl<T: serde::Deserialize> serde::Deserialize for WeightedTable<T> {
+    fn deserialize<D: serde::Deserializer>(d: D) -> Result<Self, D::Error> {
+        let entries: Vec<(f32, TableEntry<T>)> = serde::Deserialize::deserialize(d)?;
+        if entries.iter().any(|&(w, _)| !valid_weight(w)) {
+            return Err(serde::de::Error::custom("negative or infinite weight in weighted table"));
+        }
+        let mut ret = WeightedTable {
+            entries: entries,
+            prob: Vec::new(),
+            alias: Vec::new(),
+        };
+        ret.build();
+        Ok(ret)
+    }
+}
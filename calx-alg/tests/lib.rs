@@ -1,7 +1,9 @@
 extern crate serde_json;
 extern crate calx_alg;
+extern crate calx_color;
 extern crate rand;
 
+use std::cmp::max;
 use std::collections::HashMap;
 use rand::{Rng, SeedableRng, XorShiftRng};
 
@@ -9,15 +11,60 @@ use calx_alg::WeightedChoice;
 
 #[test]
 fn test_serialize_rng() {
-    use calx_alg::EncodeRng;
+    use calx_alg::{Pcg32, Xoshiro256StarStar};
 
-    let mut rng: EncodeRng<XorShiftRng> = SeedableRng::from_seed([1, 2, 3, 4]);
+    let mut rng: Pcg32 = SeedableRng::from_seed(1234);
+    rng.next_u32();
+    let saved = serde_json::to_string(&rng).expect("Serialization failed");
+    let mut rng2: Pcg32 = serde_json::from_str(&saved).expect("Deserialization failed");
+    for _ in 0..100 {
+        assert_eq!(rng.next_u32(), rng2.next_u32());
+    }
 
+    let mut rng: Xoshiro256StarStar = SeedableRng::from_seed(1234);
+    rng.next_u64();
     let saved = serde_json::to_string(&rng).expect("Serialization failed");
-    let mut rng2: EncodeRng<XorShiftRng> = serde_json::from_str(&saved)
-                                               .expect("Deserialization failed");
+    let mut rng2: Xoshiro256StarStar = serde_json::from_str(&saved)
+                                           .expect("Deserialization failed");
+    for _ in 0..100 {
+        assert_eq!(rng.next_u64(), rng2.next_u64());
+    }
 
-    assert!(rng.next_u32() == rng2.next_u32());
+    // Invalid states are rejected.
+    assert!(serde_json::from_str::<Pcg32>("[1, 2]").is_err());
+    assert!(serde_json::from_str::<Xoshiro256StarStar>("[0, 0, 0, 0]").is_err());
+}
+
+#[test]
+fn test_rng_golden_values() {
+    use calx_alg::{Pcg32, Xoshiro256StarStar};
+
+    // Values from the reference implementations. These must never change, or old save games
+    // will stop reproducing.
+    let mut rng = Pcg32::new(42, 54);
+    let values: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+    assert_eq!(vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e],
+               values);
+
+    let mut rng: Pcg32 = SeedableRng::from_seed(12345);
+    let values: Vec<u32> = (0..4).map(|_| rng.next_u32()).collect();
+    assert_eq!(vec![0x1220b391, 0x98d38aaa, 0x5bbddfa6, 0x871ffa62], values);
+
+    let mut rng = Xoshiro256StarStar::from_state([1, 2, 3, 4]);
+    let values: Vec<u64> = (0..6).map(|_| rng.next_u64()).collect();
+    assert_eq!(vec![11520,
+                    0,
+                    1509978240,
+                    1215971899390074240,
+                    1216172134540287360,
+                    607988272756665600],
+               values);
+
+    let mut rng: Xoshiro256StarStar = SeedableRng::from_seed(12345);
+    let values: Vec<u64> = (0..4).map(|_| rng.next_u64()).collect();
+    assert_eq!(vec![0xbe6a36374160d49b, 0x214aaa0637a688c6, 0xf69d16de9954d388, 0x0c60048c4e96e033],
+               values);
+    assert_eq!(0xbe6a3637, Xoshiro256StarStar::from_seed(12345).next_u32());
 }
 
 #[test]
@@ -57,6 +104,37 @@ fn test_split_line() {
     splits_into(5, "the \t cat", &["the", "cat"]);
 }
 
+#[test]
+fn test_markup() {
+    use calx_alg::{MarkupError, TextSpan, parse_markup, split_spans};
+    use calx_color::SRgba;
+
+    let red = Some(SRgba::new(0xff, 0, 0, 0xff));
+    let blue = Some(SRgba::new(0, 0, 0xff, 0xff));
+
+    assert_eq!(Ok(vec![]), parse_markup(""));
+    assert_eq!(Ok(vec![TextSpan::new("a {b}", None)]), parse_markup("a {{b}"));
+    assert_eq!(Ok(vec![TextSpan::new("Danger", red),
+                       TextSpan::new(" in ", None),
+                       TextSpan::new("the ", blue),
+                       TextSpan::new("deep", red),
+                       TextSpan::new(" sea", blue)]),
+               parse_markup("{Red}Danger{/} in {#00F}the {red}deep{/} sea"));
+    // Adjacent spans of the same color merge.
+    assert_eq!(Ok(vec![TextSpan::new("ab", red)]), parse_markup("{red}a{/}{red}b"));
+
+    assert_eq!(Err(MarkupError::UnknownColor("reddish".to_string())),
+               parse_markup("{reddish}x"));
+    assert_eq!(Err(MarkupError::UnclosedTag), parse_markup("{red"));
+    assert_eq!(Err(MarkupError::UnmatchedClose), parse_markup("x{/}"));
+
+    let spans = parse_markup("the {red}big bad{/} wolf").unwrap();
+    assert_eq!(vec![vec![TextSpan::new("the ", None), TextSpan::new("big", red)],
+                    vec![TextSpan::new("bad", red), TextSpan::new(" wolf", None)]],
+               split_spans(&spans, |_| 1.0, 8.0));
+    assert_eq!(vec![Vec::<TextSpan>::new()], split_spans(&[], |_| 1.0, 8.0));
+}
+
 #[test]
 fn test_weighted_choice() {
     let mut histogram: HashMap<u32, f32> = HashMap::new();
@@ -83,6 +161,81 @@ fn test_weighted_choice() {
     assert!(err < 0.0001);
 }
 
+/// Return the mean and variance of samples.
+fn sample_stats<F: FnMut() -> f64>(n: usize, mut f: F) -> (f64, f64) {
+    let samples: Vec<f64> = (0..n).map(|_| f()).collect();
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    let var = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / n as f64;
+    (mean, var)
+}
+
+#[test]
+fn test_distributions() {
+    use calx_alg::RngExt;
+
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    let n = 100000;
+    let check = |name: &str, (mean, var): (f64, f64), ideal_mean: f64, ideal_var: f64| {
+        println!("{}: mean {} var {}", name, mean, var);
+        assert!((mean - ideal_mean).abs() < 0.02 * (1.0 + ideal_mean.abs()),
+                "{} mean {} != {}",
+                name,
+                mean,
+                ideal_mean);
+        assert!((var - ideal_var).abs() < 0.03 * (1.0 + ideal_var),
+                "{} variance {} != {}",
+                name,
+                var,
+                ideal_var);
+    };
+
+    check("normal", sample_stats(n, || rng.normal(3.0, 2.0) as f64), 3.0, 4.0);
+    check("exponential", sample_stats(n, || rng.exponential(2.0) as f64), 0.5, 0.25);
+    check("poisson small", sample_stats(n, || rng.poisson(3.5) as f64), 3.5, 3.5);
+    check("poisson large", sample_stats(n, || rng.poisson(40.0) as f64), 40.0, 40.0);
+    check("binomial", sample_stats(n, || rng.binomial(20, 0.3) as f64), 6.0, 4.2);
+    check("binomial high p", sample_stats(n, || rng.binomial(10, 0.9) as f64), 9.0, 0.9);
+    check("triangular",
+          sample_stats(n, || rng.triangular(0.0, 1.0, 4.0) as f64),
+          5.0 / 3.0,
+          13.0 / 18.0);
+    check("beta", sample_stats(n, || rng.beta(2.0, 5.0) as f64), 2.0 / 7.0, 10.0 / 392.0);
+    check("beta small shape", sample_stats(n, || rng.beta(0.5, 0.5) as f64), 0.5, 0.125);
+
+    for _ in 0..n {
+        let x = rng.truncated_normal(0.0, 1.0, 0.5, 1.0);
+        assert!(x >= 0.5 && x <= 1.0);
+    }
+    // Impossible tail falls back to the range end.
+    assert_eq!(10.0, rng.truncated_normal(0.0, 1.0, 10.0, 11.0));
+    assert_eq!(0, rng.binomial(10, 0.0));
+    assert_eq!(10, rng.binomial(10, 1.0));
+    assert_eq!(0, rng.poisson(0.0));
+
+    // Points are inside the shapes and spread evenly between the quadrants.
+    let mut quadrants = [0; 4];
+    for _ in 0..n {
+        let (x, y) = rng.in_unit_circle();
+        assert!(x * x + y * y <= 1.0);
+        quadrants[(x > 0.0) as usize + 2 * (y > 0.0) as usize] += 1;
+
+        let (x, y) = rng.in_unit_hex();
+        let (x, y) = (x.abs(), y.abs());
+        assert!(y <= 0.8661 && y <= 3f32.sqrt() * (1.0 - x) + 0.0001);
+    }
+    for &q in &quadrants {
+        assert!((q as f32 / n as f32 - 0.25).abs() < 0.01);
+    }
+
+    // Deterministic for a given generator state.
+    let mut a: XorShiftRng = SeedableRng::from_seed([5, 6, 7, 8]);
+    let mut b = a.clone();
+    for _ in 0..100 {
+        assert_eq!(a.poisson(50.0), b.poisson(50.0));
+        assert_eq!(a.beta(0.5, 3.0), b.beta(0.5, 3.0));
+    }
+}
+
 #[test]
 fn test_random_permutation() {
     use calx_alg::RandomPermutation;
@@ -98,6 +251,66 @@ fn test_random_permutation() {
     assert_ne!(perm, sorted);
 }
 
+#[test]
+fn test_shuffle_bag() {
+    use calx_alg::ShuffleBag;
+
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    let mut bag: ShuffleBag<u32> = (0..10).collect();
+    for _ in 0..5 {
+        let mut drawn: Vec<u32> = (0..10).map(|_| *bag.draw(&mut rng).unwrap()).collect();
+        assert_eq!(0, bag.remaining());
+        drawn.sort();
+        assert_eq!((0..10).collect::<Vec<u32>>(), drawn);
+    }
+
+    bag.draw(&mut rng);
+    bag.add(10);
+    assert_eq!(10, bag.remaining());
+    let saved = serde_json::to_string(&bag).unwrap();
+    let mut loaded: ShuffleBag<u32> = serde_json::from_str(&saved).unwrap();
+    let mut rng2 = rng.clone();
+    for _ in 0..30 {
+        assert_eq!(bag.draw(&mut rng), loaded.draw(&mut rng2));
+    }
+
+    let mut empty: ShuffleBag<u32> = ShuffleBag::new(Vec::new());
+    assert_eq!(None, empty.draw(&mut rng));
+}
+
+#[test]
+fn test_pseudo_random() {
+    use calx_alg::PseudoRandom;
+
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    let mut prd = PseudoRandom::new(0.25);
+    // Known value for the increment of a 25 % chance.
+    assert!((prd.chance() - 0.0847).abs() < 0.0001);
+
+    let n = 100000;
+    let mut successes = 0;
+    let mut streak = 0;
+    let mut longest_streak = 0;
+    for _ in 0..n {
+        if prd.roll(&mut rng) {
+            successes += 1;
+            streak = 0;
+        } else {
+            streak += 1;
+            longest_streak = max(streak, longest_streak);
+        }
+    }
+    let rate = successes as f32 / n as f32;
+    assert!((rate - 0.25).abs() < 0.01, "Success rate {}", rate);
+    // The chance reaches one after at most 12 failures.
+    assert!(longest_streak <= 11);
+
+    prd.reset();
+    assert!(prd.chance() < 0.1);
+    assert_eq!(1.0, PseudoRandom::new(1.0).chance());
+    assert!(!PseudoRandom::new(0.0).roll(&mut rng));
+}
+
 #[test]
 fn test_bit_spread() {
     use calx_alg::{compact_bits_by_2, spread_bits_by_2};
@@ -109,3 +322,514 @@ fn test_bit_spread() {
         assert_eq!(compact_bits_by_2(spread_bits_by_2(x)), x);
     }
 }
+
+#[test]
+fn test_coherent_noise() {
+    use calx_alg::{DomainWarp, Fbm, Noise, Perlin, Ridged, Simplex, ValueNoise};
+
+    fn check<N: Noise>(n: &N) {
+        for i in 0..1000 {
+            let (x, y, z) = (i as f32 * 0.137, i as f32 * -0.211, i as f32 * 0.071);
+            let a = n.noise2(x, y);
+            assert!(a >= -1.0 && a <= 1.0);
+            let b = n.noise3(x, y, z);
+            assert!(b >= -1.0 && b <= 1.0);
+
+            // Coherent noise changes smoothly.
+            assert!((n.noise2(x + 0.001, y) - a).abs() < 0.05);
+            assert!((n.noise3(x, y + 0.001, z) - b).abs() < 0.05);
+        }
+    }
+
+    check(&Perlin::new(1));
+    check(&Simplex::new(1));
+    check(&ValueNoise::new(1));
+    check(&Fbm::new(Simplex::new(1)));
+    check(&Ridged::new(Perlin::new(1)));
+    check(&DomainWarp::new(Perlin::new(1), Simplex::new(2), 0.5));
+
+    // Gradient noise is zero at the lattice points.
+    assert_eq!(0.0, Perlin::new(1).noise2(3.0, -5.0));
+    assert_eq!(0.0, Perlin::new(1).noise3(3.0, -5.0, 7.0));
+
+    assert_eq!(Perlin::new(1).noise2(0.3, 0.7), Perlin::new(1).noise2(0.3, 0.7));
+    assert!(Perlin::new(1).noise2(0.3, 0.7) != Perlin::new(2).noise2(0.3, 0.7));
+
+    // The noise must stay the same on every platform and in every release.
+    let golden = [(Perlin::new(1).noise2(0.3, 0.7), -0.5178878),
+                  (Perlin::new(1).noise3(0.3, 0.7, 1.9), -0.46826547),
+                  (Simplex::new(1).noise2(0.3, 0.7), 0.3126572),
+                  (Simplex::new(1).noise3(0.3, 0.7, 1.9), 0.004881144),
+                  (ValueNoise::new(1).noise2(0.3, 0.7), -0.34451234),
+                  (ValueNoise::new(1).noise3(0.3, 0.7, 1.9), 0.3956584)];
+    for &(x, y) in &golden {
+        assert!((x - y).abs() < 1e-6, "{} != {}", x, y);
+    }
+}
+
+#[test]
+fn test_seed_derivation() {
+    use calx_alg::{RngExt, Seed};
+
+    let root = Seed::new(1234);
+    let level = root.child("level").child(7);
+
+    // Streams don't depend on what was drawn from other streams.
+    let mut other = root.child("items").rng();
+    for _ in 0..100 {
+        other.next_u32();
+    }
+    let mut a = level.rng();
+    let mut b = Seed::new(1234).child("level").child(7).rng();
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+        assert_eq!(a.coinflip(), b.coinflip());
+    }
+
+    // Order and kind of the labels matter.
+    assert!(root.child("a").child("b") != root.child("b").child("a"));
+    assert!(root.child(7) != root.child("7"));
+    assert_eq!(root.child(7u8), root.child(7i64));
+    assert!(root.child(7) != Seed::new(1235).child(7));
+
+    let saved = serde_json::to_string(&level).unwrap();
+    assert_eq!(level, serde_json::from_str(&saved).unwrap());
+
+    // The derivation is stable across releases, these values must never change.
+    assert_eq!(0xb0020f678be4f6b9, root.child("level").value());
+    assert_eq!(0x4a1b2acbec362684, level.value());
+    assert_eq!(0x5e19e24129735a95, root.child(7).value());
+    assert_eq!(0x6bab40991ea39049, level.rng().next_u64());
+}
+
+#[test]
+fn test_dice() {
+    use calx_alg::Dice;
+
+    fn dice(s: &str) -> Dice { s.parse().unwrap() }
+
+    for s in &["3d6+2", "2d20kh1", "4dF", "1d6!", "4d6kl3-1d4+1", "-2", "1d6!kh1"] {
+        assert_eq!(*s, dice(s).to_string());
+    }
+    assert_eq!("1d100-3", dice(" d% - 3").to_string());
+    assert_eq!("3d6kh2", dice("3D6k2").to_string());
+    for s in &["", "3d", "d0", "0d6", "3d6+", "2d6kh3", "1d1!", "4dF!", "3x6", "3d6 2", "3 d6"] {
+        assert!(s.parse::<Dice>().is_err(), "Parsed bad dice '{}'", s);
+    }
+    // Too many or too large dice, and results that don't fit in i32.
+    for s in &["d3000000000", "1000d4000000", "1d400000000!", "2000000000+2000000000",
+               "1d2147483647", "1000000000d1", "1d2000000000", "101d6", "1d1001",
+               "2147483000+100d1000"] {
+        assert!(s.parse::<Dice>().is_err(), "Parsed too large dice '{}'", s);
+    }
+    assert_eq!(100000, dice("100d1000").max());
+    assert_eq!(-1100000, dice("-100d1000!").min());
+
+    // Exact distributions.
+    let dist = dice("2d6").distribution();
+    assert_eq!(11, dist.len());
+    assert_eq!((7, 6.0 / 36.0), (dist[5].0, (dist[5].1 * 36.0).round() / 36.0));
+    for s in &["3d6+2", "2d20kh1", "4dF", "1d6!", "4d6kl3-1d4+1", "3d4!kh2"] {
+        let d = dice(s);
+        let dist = d.distribution();
+        assert!((dist.iter().map(|&(_, p)| p).sum::<f64>() - 1.0).abs() < 1e-9);
+        assert_eq!(d.min(), dist[0].0);
+        assert_eq!(d.max(), dist[dist.len() - 1].0);
+    }
+    assert!((dice("3d6+2").mean() - 12.5).abs() < 1e-9);
+    assert!((dice("2d20kh1").mean() - 13.825).abs() < 1e-9);
+    assert!((dice("2d20kl1").mean() - 7.175).abs() < 1e-9);
+    assert!((dice("4d6kh3").mean() - 12.244598765).abs() < 1e-6);
+    assert!(dice("4dF").mean().abs() < 1e-9);
+    assert!((dice("1d6!").mean() - 4.2).abs() < 1e-6);
+    assert_eq!((-4, 4), (dice("4dF").min(), dice("4dF").max()));
+
+    // Rolls follow the distribution.
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+    for s in &["4d6kh3", "2d4!-1", "3dF+1"] {
+        let d = dice(s);
+        let n = 100000;
+        let mut hist = HashMap::new();
+        for _ in 0..n {
+            let x = d.roll(&mut rng);
+            assert!(x >= d.min() && x <= d.max());
+            *hist.entry(x).or_insert(0) += 1;
+        }
+        for (x, p) in d.distribution() {
+            let observed = *hist.get(&x).unwrap_or(&0) as f64 / n as f64;
+            assert!((observed - p).abs() < 0.01, "{}: P({}) {} != {}", s, x, observed, p);
+        }
+    }
+
+    let saved = serde_json::to_string(&dice("2d20kh1+3")).unwrap();
+    assert_eq!("\"2d20kh1+3\"", saved);
+    assert_eq!(dice("2d20kh1+3"), serde_json::from_str(&saved).unwrap());
+    assert!(serde_json::from_str::<Dice>("\"2d\"").is_err());
+}
+
+#[test]
+fn test_weighted_table() {
+    use calx_alg::WeightedTable;
+
+    let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+
+    let mut gems = WeightedTable::new();
+    gems.add(1.0, 5u32);
+    gems.add(3.0, 6);
+    let mut table: WeightedTable<u32> = vec![(1.0, 1u32), (2.0, 2), (0.0, 3), (3.0, 4)]
+                                            .into_iter()
+                                            .collect();
+    table.add_table(4.0, gems);
+    assert_eq!(10.0, table.total_weight());
+
+    let n = 100000;
+    let mut histogram: HashMap<u32, f32> = HashMap::new();
+    for _ in 0..n {
+        *histogram.entry(*table.sample(&mut rng).unwrap()).or_insert(0.0) += 1.0;
+    }
+    assert_eq!(None, histogram.get(&3));
+    for &(x, p) in &[(1, 0.1), (2, 0.2), (4, 0.3), (5, 0.1), (6, 0.3)] {
+        let observed = histogram[&x] / n as f32;
+        assert!((observed - p).abs() < 0.01, "P({}) {} != {}", x, observed, p);
+    }
+
+    // Tables survive serialization.
+    let saved = serde_json::to_string(&table).unwrap();
+    let loaded: WeightedTable<u32> = serde_json::from_str(&saved).unwrap();
+    assert_eq!(table, loaded);
+    assert!(serde_json::from_str::<WeightedTable<u32>>("[[-1.0, {\"Item\": 1}]]").is_err());
+
+    // Sampling without replacement draws every positive weight item once.
+    let mut drawn = Vec::new();
+    while let Some(x) = table.take(&mut rng) {
+        drawn.push(x);
+    }
+    drawn.sort();
+    assert_eq!(vec![1, 2, 4, 5, 6], drawn);
+    assert_eq!(1, table.len());
+    assert_eq!(None, table.sample(&mut rng));
+
+    let empty: WeightedTable<u32> = WeightedTable::new();
+    assert_eq!(None, empty.sample(&mut rng));
+
+    // A sub-table with only zero weights doesn't stop the other items from being drawn.
+    for _ in 0..20 {
+        let mut table: WeightedTable<u32> = vec![(1.0, 1u32)].into_iter().collect();
+        table.add_table(1.0, vec![(0.0, 2u32)].into_iter().collect());
+        assert_eq!(Some(1), table.take(&mut rng));
+        assert_eq!(None, table.take(&mut rng));
+        assert!(table.is_empty());
+    }
+}
+
+#[test]
+fn test_easing() {
+    use calx_alg::ease;
+    use calx_alg::ease::Easing::*;
+
+    let all = [Linear, QuadraticIn, QuadraticOut, QuadraticInOut, CubicIn, CubicOut, CubicInOut,
+               SinIn, SinOut, SinInOut, ExpoIn, ExpoOut, ExpoInOut, CircIn, CircOut, CircInOut,
+               BackIn, BackOut, BackInOut, ElasticIn, ElasticOut, ElasticInOut, BounceIn,
+               BounceOut, BounceInOut, CubicBezier(0.25, 0.1, 0.25, 1.0), StepsIn(4),
+               StepsOut(4), StepsInOut(4)];
+    for e in &all {
+        assert!(e.apply(0.0).abs() < 1e-5, "{:?} starts at {}", e, e.apply(0.0));
+        assert!((e.apply(1.0) - 1.0).abs() < 1e-5, "{:?} ends at {}", e, e.apply(1.0));
+
+        // In and out curves are symmetric, in-out curves pass through the middle.
+        let name = format!("{:?}", e);
+        if name.ends_with("InOut") {
+            assert!((e.apply(0.5) - 0.5).abs() < 1e-5, "{:?} middle {}", e, e.apply(0.5));
+        }
+
+        let saved = serde_json::to_string(e).unwrap();
+        assert_eq!(*e, serde_json::from_str(&saved).unwrap());
+    }
+
+    assert_eq!(0.765625, ease::bounce_out(0.5));
+    assert!((ease::bounce_in(0.3) - (1.0 - ease::bounce_out(0.7))).abs() < 1e-6);
+    assert!(ease::back_in(0.2) < 0.0);
+    assert!(ease::back_out(0.8) > 1.0);
+    assert!((0..100).any(|i| ease::elastic_out(i as f32 / 100.0) > 1.0));
+    assert!((ease::expo_out(0.3) - (1.0 - ease::expo_in(0.7))).abs() < 1e-6);
+    assert!((ease::circ_out(0.3) - (1.0 - ease::circ_in(0.7))).abs() < 1e-6);
+
+    assert_eq!(0.25, ease::steps_in(4, 0.3));
+    assert_eq!(0.5, ease::steps_out(4, 0.3));
+    assert_eq!(0.0, ease::steps_in(4, 0.2));
+    assert_eq!(0.25, ease::steps_out(4, 0.2));
+    // Control point x coordinates outside [0, 1] are clamped.
+    let bad = CubicBezier(-0.5, 0.1, 1.5, 1.0);
+    assert_eq!(CubicBezier(0.0, 0.1, 1.0, 1.0).apply(0.3), bad.apply(0.3));
+
+    // Zero steps is one step.
+    for &e in &[StepsIn(0), StepsOut(0), StepsInOut(0)] {
+        for &t in &[0.0, 0.3, 0.7, 1.0] {
+            assert!(e.apply(t).is_finite());
+        }
+    }
+    assert_eq!(ease::steps_in(1, 0.5), ease::steps_in(0, 0.5));
+    assert_eq!(ease::steps_out(1, 0.5), ease::steps_out(0, 0.5));
+
+    // CSS "ease" curve.
+    assert!((ease::cubic_bezier(0.25, 0.1, 0.25, 1.0, 0.5) - 0.8024).abs() < 1e-3);
+    // Straight line control points give linear easing.
+    for i in 0..11 {
+        let t = i as f32 / 10.0;
+        assert!((ease::cubic_bezier(0.3, 0.3, 0.7, 0.7, t) - t).abs() < 1e-4);
+    }
+    // Flat spots in the curve still converge.
+    assert!((ease::cubic_bezier(1.0, 0.0, 0.0, 1.0, 0.5) - 0.5).abs() < 1e-3);
+}
+
+#[test]
+fn test_tween() {
+    use calx_alg::{KeyframeTrack, Tween};
+    use calx_alg::TweenEvent::*;
+    use calx_alg::ease::Easing;
+
+    let mut tween = Tween::new(10.0f32, 20.0, 4.0).delay(1.0);
+    assert_eq!(10.0, tween.value());
+    assert!(tween.update(0.5).is_empty());
+    assert_eq!(vec![Started], tween.update(1.5));
+    assert_eq!(12.5, tween.value());
+    assert_eq!(vec![Finished], tween.update(10.0));
+    assert!(tween.is_finished());
+    assert_eq!(20.0, tween.value());
+    assert!(tween.update(1.0).is_empty());
+    tween.reset();
+    assert_eq!(10.0, tween.value());
+
+    // Big steps report every loop.
+    let mut tween = Tween::new(0.0f32, 1.0, 1.0).looping().repeat(3);
+    assert_eq!(vec![Started, Looped, Looped], tween.update(2.5));
+    assert_eq!(0.5, tween.value());
+    assert_eq!(vec![Finished], tween.update(2.5));
+    assert_eq!(1.0, tween.value());
+
+    let mut tween = Tween::new(0.0f32, 1.0, 1.0).ping_pong();
+    tween.update(0.25);
+    assert_eq!(0.25, tween.value());
+    tween.update(1.0);
+    assert_eq!(0.75, tween.value());
+    assert_eq!(vec![Looped], tween.update(1.0));
+    assert_eq!(0.25, tween.value());
+    assert!(!tween.is_finished());
+
+    // Endless animations keep their precision after a long time.
+    for &ping_pong in &[false, true] {
+        let tween = Tween::new(0.0f32, 1.0, 1000.0);
+        let mut tween = if ping_pong { tween.ping_pong() } else { tween.looping() };
+        tween.update(4200500.0);
+        assert_eq!(0.5, tween.value());
+        tween.update(0.25);
+        assert!(tween.value() > 0.5);
+    }
+
+    // A long update of an endless animation reports a single loop.
+    let mut tween = Tween::new(0.0f32, 1.0, 0.001).looping();
+    assert_eq!(vec![Started, Looped], tween.update(1e9));
+    assert_eq!(vec![Looped], tween.update(1e6));
+
+    // Repeating a tween that plays once makes it loop.
+    let mut tween = Tween::new(0.0f32, 1.0, 1.0).repeat(2);
+    assert_eq!(vec![Started, Looped], tween.update(1.5));
+    assert_eq!(0.5, tween.value());
+
+    let mut tween = Tween::new(0.0f32, 1.0, 2.0).easing(Easing::QuadraticIn);
+    tween.update(1.0);
+    assert_eq!(0.25, tween.value());
+
+    // Zero length tweens finish right away.
+    let mut tween = Tween::new(0.0f32, 1.0, 0.0);
+    assert_eq!(vec![Started, Finished], tween.update(0.1));
+    assert_eq!(1.0, tween.value());
+
+    let track = KeyframeTrack::new(0.0f32)
+                    .then(2.0, 4.0, Easing::Linear)
+                    .hold(1.0)
+                    .then(1.0, 0.0, Easing::QuadraticIn);
+    assert_eq!(4.0, track.duration());
+    assert_eq!(0.0, track.sample(-1.0));
+    assert_eq!(1.0, track.sample(0.5));
+    assert_eq!(4.0, track.sample(2.5));
+    assert_eq!(3.0, track.sample(3.5));
+    assert_eq!(0.0, track.sample(5.0));
+
+    let mut looped = track.clone().ping_pong().repeat(2).delay(1.0);
+    looped.update(2.0);
+    assert_eq!(2.0, looped.value());
+    assert!(looped.update(2.5).is_empty());
+    // Half a time unit on the way back.
+    assert_eq!(3.0, looped.value());
+    assert_eq!(vec![Looped], looped.update(5.5));
+    assert_eq!(2.0, looped.value());
+    assert_eq!(vec![Finished], looped.update(100.0));
+    assert_eq!(0.0, looped.value());
+
+    let saved = serde_json::to_string(&looped).unwrap();
+    assert_eq!(looped, serde_json::from_str(&saved).unwrap());
+}
+
+#[test]
+fn test_clock() {
+    use std::rc::Rc;
+    use calx_alg::timing::{AverageDuration, Clock, ManualClock, ScaledClock, Ticker,
+                           TimePerFrame, cycle_anim_with, single_anim_with, spike_with};
+
+    let clock = Rc::new(ManualClock::new(10.0));
+    let mut ticker = Ticker::with_clock(clock.clone(), 0.5);
+    assert!(!ticker.get());
+    clock.advance(0.6);
+    assert!(ticker.get());
+    assert!(!ticker.get());
+    // Sleeping on a manual clock just moves it forward.
+    ticker.wait_for_tick();
+    assert_eq!(11.0, clock.now());
+
+    let mut frame = TimePerFrame::with_clock(clock.clone(), 1.0);
+    frame.begin();
+    clock.advance(0.25);
+    frame.end();
+    assert_eq!(0.25, frame.last);
+    assert_eq!(0.25, frame.average);
+
+    let mut average = AverageDuration::with_clock(&*clock, 1.0, 0.5);
+    clock.advance(2.0);
+    average.tick();
+    assert_eq!(1.5, average.value);
+
+    let frames = [0, 1, 2, 3];
+    clock.set(2.6);
+    assert_eq!(2, *cycle_anim_with(&*clock, 0.25, &frames));
+    assert_eq!(3, *single_anim_with(&*clock, 1.0, 0.25, &frames));
+    assert_eq!(0, *single_anim_with(&*clock, 5.0, 0.25, &frames));
+    assert!(spike_with(&*clock, 0.5, 0.5));
+    assert!(!spike_with(&*clock, 1.0, 1.0));
+
+    let scaled = ScaledClock::new(clock.clone());
+    assert_eq!(2.6, scaled.now());
+    scaled.set_scale(2.0);
+    clock.advance(1.0);
+    assert_eq!(4.6, scaled.now());
+    scaled.pause();
+    clock.advance(1.0);
+    assert_eq!(4.6, scaled.now());
+    scaled.resume();
+    scaled.set_scale(0.5);
+    clock.advance(1.0);
+    assert_eq!(5.1, scaled.now());
+    // Sleeping waits for the source time that matches the scaled time.
+    scaled.sleep(1.0);
+    assert_eq!(7.6, clock.now());
+    assert_eq!(6.1, scaled.now());
+}
+
+#[test]
+fn test_fixed_timestep() {
+    use calx_alg::timing::{FixedTimestep, ManualClock, TimestepStats};
+
+    let clock = ManualClock::new(0.0);
+    let mut timestep = FixedTimestep::with_clock(&clock, 0.25, 4);
+    assert_eq!(0, timestep.advance());
+
+    clock.advance(0.6);
+    assert_eq!(2, timestep.advance());
+    assert!((timestep.alpha() - 0.4).abs() < 1e-9);
+
+    clock.advance(0.15);
+    assert_eq!(1, timestep.advance());
+    assert!(timestep.alpha() < 1e-9);
+
+    // A long stall only runs the maximum number of updates.
+    clock.advance(2.1);
+    assert_eq!(4, timestep.advance());
+    assert!((timestep.alpha() - 0.4).abs() < 1e-9);
+    assert_eq!(TimestepStats {
+                   frames: 4,
+                   updates: 7,
+                   dropped: 4,
+               },
+               timestep.stats());
+
+    // Waiting runs headless with a manual clock.
+    assert!((timestep.time_to_update() - 0.15).abs() < 1e-9);
+    timestep.wait_for_update();
+    assert_eq!(1, timestep.advance());
+
+    clock.advance(10.0);
+    timestep.reset();
+    assert_eq!(0, timestep.advance());
+    assert_eq!(0.0, timestep.alpha());
+}
+
+#[test]
+fn test_scheduler() {
+    use calx_alg::{Scheduler, Turn};
+
+    fn run(scheduler: &mut Scheduler<u32, String>, until: u64) -> Vec<String> {
+        let mut log = Vec::new();
+        while scheduler.next_time().map_or(false, |t| t <= until) {
+            match scheduler.next().unwrap() {
+                Turn::Actor(a) => {
+                    log.push(format!("{}@{}", a, scheduler.now()));
+                    scheduler.act(&a, 100);
+                }
+                Turn::Event(e) => log.push(format!("{}@{}", e, scheduler.now())),
+            }
+        }
+        log
+    }
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add_actor(1, 100, 0);
+    scheduler.add_actor(2, 150, 0);
+    scheduler.add_actor(3, 100, 0);
+    let poison = scheduler.schedule(50, "poison".to_string());
+    let _ = scheduler.schedule(100, "tick".to_string());
+    let cancelled = scheduler.schedule(100, "cancelled".to_string());
+    assert_eq!(Some("cancelled".to_string()), scheduler.cancel(cancelled));
+    assert_eq!(None, scheduler.cancel(cancelled));
+
+    // Ties go in scheduling order, speed 150 gets three turns for every two normal ones.
+    assert_eq!(run(&mut scheduler, 200),
+               vec!["1@0", "2@0", "3@0", "poison@50", "2@66", "tick@100", "1@100", "3@100",
+                    "2@133", "1@200", "3@200", "2@200"]);
+    assert_eq!(None, scheduler.cancel(poison));
+
+    scheduler.remove_actor(&3);
+    scheduler.set_speed(&1, 50);
+    assert_eq!(Some(50), scheduler.speed(&1));
+    assert_eq!(None, scheduler.speed(&3));
+
+    // Saved games continue the same way.
+    let saved = serde_json::to_string(&scheduler).unwrap();
+    let mut loaded: Scheduler<u32, String> = serde_json::from_str(&saved).unwrap();
+    assert_eq!(scheduler, loaded);
+    let log = run(&mut scheduler, 600);
+    assert_eq!(log, run(&mut loaded, 600));
+    assert!(!log.iter().any(|e| e.starts_with("3@")));
+    assert_eq!(vec!["2@266", "1@300"], log[..2].to_vec());
+
+    // Inconsistent saves are rejected.
+    fn loads(next_seq: u64, speed: u32, turn_seq: u64, actor: u32) -> bool {
+        let json = format!("{{\"now\":0,\"next_seq\":{},\"queue\":[[{{\"time\":5,\"seq\":0}},\
+                            {{\"Actor\":1}}],[{{\"time\":7,\"seq\":1}},{{\"Event\":\"x\"}}]],\
+                            \"actors\":[[{},{{\"speed\":{},\"turn\":{{\"time\":5,\"seq\":{}}},\
+                            \"remainder\":0}}]]}}",
+                           next_seq,
+                           actor,
+                           speed,
+                           turn_seq);
+        serde_json::from_str::<Scheduler<u32, String>>(&json).is_ok()
+    }
+    assert!(loads(2, 100, 0, 1));
+    // Sequence number already used.
+    assert!(!loads(1, 100, 0, 1));
+    // Zero speed.
+    assert!(!loads(2, 0, 0, 1));
+    // Actor turn points at the wrong queue slot.
+    assert!(!loads(2, 100, 1, 1));
+    // Queued actor is missing.
+    assert!(!loads(2, 100, 0, 2));
+}
@@ -22,7 +22,7 @@ use num::Num;
 /// alpha channel and with 4 or 8 bits per channel). "RED", "red",
 /// "#F00", "#F00F", "#FF0000" and "#FF0000FF" all correspond to the
 /// same opaque pure red color.
-#[derive(Copy, Clone, PartialEq, PartialOrd, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Ord, PartialOrd, Debug, Serialize, Deserialize)]
 pub struct SRgba {
     /// sRGB red component
     pub r: u8,
@@ -78,6 +78,10 @@ impl Into<image::Rgba<u8>> for SRgba {
     }
 }
 
+impl From<image::Rgba<u8>> for SRgba {
+    fn from(c: image::Rgba<u8>) -> SRgba { SRgba::new(c.data[0], c.data[1], c.data[2], c.data[3]) }
+}
+
 impl FromStr for SRgba {
     type Err = ();
 
@@ -0,0 +1,304 @@
+//! Cellular automata cave generation.
+
+use std::collections::VecDeque;
+use rand::Rng;
+use euclid::{Point2D, Size2D};
+use hex::Dir6;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+use search::{GridNode, MapNode, astar_path_with};
+
+/// Cellular automata cave generator.
+///
+/// The map starts out as random noise and is then repeatedly smoothed with a cellular automaton.
+/// The automaton counts the open cells in the Moore neighborhood of eight cells in the dense
+/// layout and in the six cell hex neighborhood in the hex layout. Cells outside the map count as
+/// walls and the map edge is always left as wall.
+///
+/// After smoothing, caves smaller than `min_cave_size` are filled in and the rest are joined
+/// with tunnels.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CaveGenerator {
+    /// Size of the generated map.
+    pub size: Size2D<u32>,
+    pub layout: MapLayout,
+    /// Chance for each cell to start out open.
+    pub initial_open: f32,
+    /// Open neighbor counts that turn a wall cell open.
+    pub birth: Vec<u32>,
+    /// Open neighbor counts that keep an open cell open.
+    pub survival: Vec<u32>,
+    /// Number of automaton steps to run.
+    pub iterations: u32,
+    /// Caves with fewer cells than this are filled in.
+    pub min_cave_size: usize,
+}
+
+impl CaveGenerator {
+    /// Create a generator with rules that work well for the layout.
+    pub fn new(size: Size2D<u32>, layout: MapLayout) -> CaveGenerator {
+        let (initial_open, birth, survival) = match layout {
+            MapLayout::Dense => (0.55, vec![5, 6, 7, 8], vec![4, 5, 6, 7, 8]),
+            MapLayout::Hex => (0.55, vec![4, 5, 6], vec![3, 4, 5, 6]),
+        };
+
+        CaveGenerator {
+            size: size,
+            layout: layout,
+            initial_open: initial_open,
+            birth: birth,
+            survival: survival,
+            iterations: 4,
+            min_cave_size: 16,
+        }
+    }
+
+    /// Generate a cave map where open cells are `true`.
+    ///
+    /// The same random number generator state always produces the same map.
+    pub fn generate<R: Rng>(&self, rng: &mut R) -> Prefab<bool> {
+        let mut grid = Grid {
+            size: self.size,
+            cells: (0..self.size.width * self.size.height)
+                       .map(|_| rng.gen::<f32>() < self.initial_open)
+                       .collect(),
+        };
+        grid.close_edges();
+
+        for _ in 0..self.iterations {
+            grid = self.step(&grid);
+        }
+
+        let mut caves = grid.caves(self.layout);
+        for cave in &caves {
+            if cave.len() < self.min_cave_size {
+                for &p in cave {
+                    grid.set(p, false);
+                }
+            }
+        }
+        caves.retain(|c| c.len() >= self.min_cave_size);
+        self.join(&mut grid, caves);
+
+        grid.to_prefab()
+    }
+
+    /// Run one step of the cellular automaton.
+    fn step(&self, grid: &Grid) -> Grid {
+        let offsets: Vec<Point2D<i32>> = match self.layout {
+            MapLayout::Dense => {
+                vec![Point2D::new(-1, -1),
+                     Point2D::new(0, -1),
+                     Point2D::new(1, -1),
+                     Point2D::new(-1, 0),
+                     Point2D::new(1, 0),
+                     Point2D::new(-1, 1),
+                     Point2D::new(0, 1),
+                     Point2D::new(1, 1)]
+            }
+            MapLayout::Hex => Dir6::iter().map(|d| d.to_v2()).collect(),
+        };
+
+        let mut ret = grid.clone();
+        for y in 0..self.size.height as i32 {
+            for x in 0..self.size.width as i32 {
+                let p = Point2D::new(x, y);
+                let n = offsets.iter().filter(|&&d| grid.is_open(p + d)).count() as u32;
+                let rule = if grid.is_open(p) { &self.survival } else { &self.birth };
+                ret.set(p, rule.contains(&n));
+            }
+        }
+        ret.close_edges();
+        ret
+    }
+
+    /// Dig tunnels to join caves.
+    ///
+    /// The largest cave is the starting point and the closest remaining cave is joined to the
+    /// connected ones until all are connected.
+    fn join(&self, grid: &mut Grid, mut caves: Vec<Vec<Point2D<i32>>>) {
+        if caves.is_empty() {
+            return;
+        }
+        // Stable sort, so ties are kept in map order.
+        caves.sort_by(|a, b| b.len().cmp(&a.len()));
+
+        let mut connected = caves.remove(0);
+        while !caves.is_empty() {
+            let mut best = None;
+            for (i, cave) in caves.iter().enumerate() {
+                for &a in cave {
+                    for &b in &connected {
+                        let d = self.node(a).distance(&self.node(b));
+                        if best.map_or(true, |(best_d, _, _, _)| d < best_d) {
+                            best = Some((d, i, a, b));
+                        }
+                    }
+                }
+            }
+
+            let (_, i, a, b) = best.unwrap();
+            // The shortest path stays within the bounding box of the end points, so it will not
+            // run along the map edge.
+            let limit = self.size.width * self.size.height * 4;
+            let path = astar_path_with(|a, b| a.distance(b), self.node(a), self.node(b), limit)
+                           .expect("Tunnel routing failed");
+            for n in path {
+                grid.set(n.pos(), true);
+                connected.push(n.pos());
+            }
+            connected.extend(caves.remove(i));
+        }
+    }
+
+    fn node(&self, pos: Point2D<i32>) -> MapNode { MapNode::new(pos, self.layout) }
+}
+
+#[derive(Clone)]
+struct Grid {
+    size: Size2D<u32>,
+    cells: Vec<bool>,
+}
+
+impl Grid {
+    fn index(&self, p: Point2D<i32>) -> Option<usize> {
+        if p.x < 0 || p.y < 0 || p.x >= self.size.width as i32 || p.y >= self.size.height as i32 {
+            None
+        } else {
+            Some((p.x + p.y * self.size.width as i32) as usize)
+        }
+    }
+
+    fn is_open(&self, p: Point2D<i32>) -> bool { self.index(p).map_or(false, |i| self.cells[i]) }
+
+    fn set(&mut self, p: Point2D<i32>, open: bool) {
+        if let Some(i) = self.index(p) {
+            self.cells[i] = open;
+        }
+    }
+
+    fn close_edges(&mut self) {
+        let (w, h) = (self.size.width as i32, self.size.height as i32);
+        for x in 0..w {
+            self.set(Point2D::new(x, 0), false);
+            self.set(Point2D::new(x, h - 1), false);
+        }
+        for y in 0..h {
+            self.set(Point2D::new(0, y), false);
+            self.set(Point2D::new(w - 1, y), false);
+        }
+    }
+
+    /// Return the connected open areas in map order.
+    fn caves(&self, layout: MapLayout) -> Vec<Vec<Point2D<i32>>> {
+        let mut seen = vec![false; self.cells.len()];
+        let mut ret = Vec::new();
+
+        for y in 0..self.size.height as i32 {
+            for x in 0..self.size.width as i32 {
+                let start = Point2D::new(x, y);
+                let i = self.index(start).unwrap();
+                if seen[i] || !self.cells[i] {
+                    continue;
+                }
+
+                let mut cave = Vec::new();
+                let mut edge = VecDeque::new();
+                seen[i] = true;
+                edge.push_back(MapNode::new(start, layout));
+                while let Some(node) = edge.pop_front() {
+                    cave.push(node.pos());
+                    for n in node.neighbors() {
+                        if !self.is_open(n.pos()) {
+                            continue;
+                        }
+                        let j = self.index(n.pos()).unwrap();
+                        if !seen[j] {
+                            seen[j] = true;
+                            edge.push_back(n);
+                        }
+                    }
+                }
+                ret.push(cave);
+            }
+        }
+
+        ret
+    }
+
+    fn to_prefab(&self) -> Prefab<bool> {
+        let mut cells = Vec::new();
+        for y in 0..self.size.height as i32 {
+            for x in 0..self.size.width as i32 {
+                let p = Point2D::new(x, y);
+                cells.push((p, self.is_open(p)));
+            }
+        }
+        cells.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, XorShiftRng};
+    use euclid::{Point2D, Size2D};
+    use prefab_file::MapLayout;
+    use super::{CaveGenerator, Grid};
+
+    #[test]
+    fn test_caves() {
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let gen = CaveGenerator::new(Size2D::new(60, 40), layout);
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            let map = gen.generate(&mut rng);
+            assert_eq!(Size2D::new(60, 40), map.dim());
+
+            let grid = Grid {
+                size: map.dim(),
+                cells: (0..60 * 40)
+                           .map(|i| map.get(Point2D::new(i % 60, i / 60)) == Some(&true))
+                           .collect(),
+            };
+            let caves = grid.caves(layout);
+            assert_eq!(1, caves.len());
+            assert!(caves[0].len() >= gen.min_cave_size);
+            // Not all wall and not all open.
+            assert!(caves[0].len() > 200 && caves[0].len() < 60 * 40 / 2 + 400);
+
+            for (p, &open) in map.iter() {
+                if p.x == 0 || p.y == 0 || p.x == 59 || p.y == 39 {
+                    assert!(!open);
+                }
+            }
+
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            assert_eq!(map, gen.generate(&mut rng));
+        }
+    }
+
+    #[test]
+    fn test_rules() {
+        // A cell with two open hex neighbors.
+        let grid = Grid {
+            size: Size2D::new(3, 3),
+            cells: vec![true, true, false, false, false, false, false, false, false],
+        };
+        let mut gen = CaveGenerator::new(Size2D::new(3, 3), MapLayout::Hex);
+        gen.birth = vec![2];
+        assert!(gen.step(&grid).is_open(Point2D::new(1, 1)));
+        gen.birth = vec![3];
+        assert!(!gen.step(&grid).is_open(Point2D::new(1, 1)));
+
+        // The top right corner is a Moore neighbor but not a hex neighbor.
+        let grid = Grid {
+            size: Size2D::new(3, 3),
+            cells: vec![false, false, true, false, false, false, false, false, false],
+        };
+        let mut gen = CaveGenerator::new(Size2D::new(3, 3), MapLayout::Dense);
+        gen.birth = vec![1];
+        assert!(gen.step(&grid).is_open(Point2D::new(1, 1)));
+        let mut gen = CaveGenerator::new(Size2D::new(3, 3), MapLayout::Hex);
+        gen.birth = vec![1];
+        assert!(!gen.step(&grid).is_open(Point2D::new(1, 1)));
+    }
+}
@@ -0,0 +1,455 @@
+//! Room-and-corridor dungeon generation.
+
+use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use rand::Rng;
+use euclid::{Point2D, Rect, Size2D};
+use prefab::Prefab;
+use prefab_file::MapLayout;
+use search::{Dijkstra, MapNode, astar_path_with};
+
+/// Terrain that the dungeon generator can build maps from.
+pub trait DungeonTerrain: Clone + Eq + Hash {
+    /// Solid terrain outside the rooms and corridors.
+    fn wall() -> Self;
+
+    /// Room floor.
+    fn floor() -> Self;
+
+    /// Corridor floor.
+    fn corridor() -> Self { Self::floor() }
+
+    /// Return whether the terrain can be walked on.
+    fn is_passable(&self) -> bool { *self != Self::wall() }
+}
+
+/// Errors from dungeon generation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DungeonError {
+    /// No corridor could be routed between two rooms.
+    CorridorFailed,
+    /// Some passable cells of the generated map can't be reached from the others.
+    Disconnected,
+}
+
+impl fmt::Display for DungeonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DungeonError::CorridorFailed => write!(f, "Could not route a corridor between rooms"),
+            DungeonError::Disconnected => write!(f, "Generated dungeon is not connected"),
+        }
+    }
+}
+
+impl error::Error for DungeonError {
+    fn description(&self) -> &str {
+        match *self {
+            DungeonError::CorridorFailed => "corridor routing failed",
+            DungeonError::Disconnected => "disconnected dungeon",
+        }
+    }
+}
+
+/// How the dungeon generator places rooms.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum RoomPlacement {
+    /// Split the map recursively into two parts until the parts are small enough and put a room
+    /// in each part. If there are more parts than the maximum number of rooms, rooms are dropped
+    /// at random.
+    Bsp,
+    /// Try random room positions and reject the ones that hit existing rooms.
+    Random {
+        /// Number of positions to try.
+        attempts: u32,
+    },
+}
+
+/// Room-and-corridor dungeon generator.
+///
+/// Rooms are rectangles in map coordinates, so in the hex layout they are rhombuses. The rooms
+/// are joined by corridors routed with A* so that every room is reachable from every other room.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DungeonGenerator {
+    /// Size of the generated map.
+    pub size: Size2D<u32>,
+    pub layout: MapLayout,
+    pub placement: RoomPlacement,
+    /// Smallest room width and height.
+    pub min_room: u32,
+    /// Largest room width and height.
+    pub max_room: u32,
+    /// Maximum number of rooms.
+    pub max_rooms: usize,
+    /// Number of corridors added between random rooms after all rooms are connected.
+    pub extra_corridors: usize,
+}
+
+impl Default for DungeonGenerator {
+    fn default() -> DungeonGenerator {
+        DungeonGenerator {
+            size: Size2D::new(80, 40),
+            layout: MapLayout::Dense,
+            placement: RoomPlacement::Bsp,
+            min_room: 3,
+            max_room: 10,
+            max_rooms: 20,
+            extra_corridors: 2,
+        }
+    }
+}
+
+/// A generated dungeon.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Dungeon<T> {
+    /// Terrain for every cell of the map area.
+    pub map: Prefab<T>,
+    /// Floor areas of the rooms.
+    pub rooms: Vec<Rect<i32>>,
+    pub layout: MapLayout,
+}
+
+impl<T: DungeonTerrain> Dungeon<T> {
+    /// Return whether every passable cell can be reached from every other passable cell.
+    pub fn is_connected(&self) -> bool {
+        let cells: Vec<MapNode> = self.map
+                                      .iter()
+                                      .filter(|&(_, t)| t.is_passable())
+                                      .map(|(p, _)| MapNode::new(p, self.layout))
+                                      .collect();
+        if cells.is_empty() {
+            return true;
+        }
+
+        let fill = Dijkstra::new(vec![cells[0]],
+                                 |n| self.map.get(n.pos()).map_or(false, |t| t.is_passable()),
+                                 cells.len() as u32);
+        cells.iter().all(|n| fill.distance(n).is_some())
+    }
+}
+
+impl DungeonGenerator {
+    /// Generate a new dungeon.
+    ///
+    /// The same random number generator state always produces the same dungeon. Returns an error
+    /// if the rooms could not be joined into a connected map.
+    pub fn generate<T: DungeonTerrain, R: Rng>(&self,
+                                               rng: &mut R)
+                                               -> Result<Dungeon<T>, DungeonError> {
+        assert!(self.min_room > 0 && self.min_room <= self.max_room);
+
+        let rooms = match self.placement {
+            RoomPlacement::Bsp => {
+                let mut rooms = Vec::new();
+                let area = Rect::new(Point2D::new(0, 0),
+                                     Size2D::new(self.size.width as i32, self.size.height as i32));
+                self.split(rng, area, &mut rooms);
+                // Drop random rooms, the split order would favor the first parts of the map.
+                while rooms.len() > self.max_rooms {
+                    let i = rng.gen_range(0, rooms.len());
+                    rooms.remove(i);
+                }
+                rooms
+            }
+            RoomPlacement::Random { attempts } => self.place_random(rng, attempts),
+        };
+
+        let mut floor = HashSet::new();
+        let mut corridor = HashSet::new();
+        for room in &rooms {
+            for y in room.min_y()..room.max_y() {
+                for x in room.min_x()..room.max_x() {
+                    floor.insert(Point2D::new(x, y));
+                }
+            }
+        }
+
+        // Connect the rooms into a spanning tree, always joining the closest unconnected room.
+        if !rooms.is_empty() {
+            let mut connected = vec![0];
+            while connected.len() < rooms.len() {
+                let (a, b) = connected.iter()
+                                      .flat_map(|&a| {
+                                          (0..rooms.len())
+                                              .filter(|b| !connected.contains(b))
+                                              .map(move |b| (a, b))
+                                      })
+                                      .min_by_key(|&(a, b)| {
+                                          self.node(center(&rooms[a]))
+                                              .distance(&self.node(center(&rooms[b])))
+                                      })
+                                      .unwrap();
+                self.dig(rng, &rooms[a], &rooms[b], &mut corridor)?;
+                connected.push(b);
+            }
+
+            if rooms.len() > 1 {
+                for _ in 0..self.extra_corridors {
+                    let a = rng.gen_range(0, rooms.len());
+                    let b = rng.gen_range(0, rooms.len());
+                    if a != b {
+                        self.dig(rng, &rooms[a], &rooms[b], &mut corridor)?;
+                    }
+                }
+            }
+        }
+
+        let mut cells = Vec::new();
+        for y in 0..self.size.height as i32 {
+            for x in 0..self.size.width as i32 {
+                let p = Point2D::new(x, y);
+                let t = if floor.contains(&p) {
+                    T::floor()
+                } else if corridor.contains(&p) {
+                    T::corridor()
+                } else {
+                    T::wall()
+                };
+                cells.push((p, t));
+            }
+        }
+
+        let ret = Dungeon {
+            map: cells.into_iter().collect(),
+            rooms: rooms,
+            layout: self.layout,
+        };
+        if !ret.is_connected() {
+            return Err(DungeonError::Disconnected);
+        }
+        Ok(ret)
+    }
+
+    fn node(&self, pos: Point2D<i32>) -> MapNode { MapNode::new(pos, self.layout) }
+
+    /// Return a random room size that fits in the given space.
+    fn room_size<R: Rng>(&self, rng: &mut R, space: Size2D<i32>) -> Option<Size2D<i32>> {
+        let max_w = min(self.max_room as i32, space.width);
+        let max_h = min(self.max_room as i32, space.height);
+        let min_room = self.min_room as i32;
+        if max_w < min_room || max_h < min_room {
+            return None;
+        }
+        Some(Size2D::new(rng.gen_range(min_room, max_w + 1),
+                         rng.gen_range(min_room, max_h + 1)))
+    }
+
+    /// Split an area recursively and add a room to every leaf area.
+    ///
+    /// Rooms are kept one cell away from the edges of the areas so that there's always a wall
+    /// between them.
+    fn split<R: Rng>(&self, rng: &mut R, area: Rect<i32>, rooms: &mut Vec<Rect<i32>>) {
+        let min_part = self.min_room as i32 + 2;
+        let max_part = self.max_room as i32 + 2;
+        let can_split_x = area.size.width >= min_part * 2;
+        let can_split_y = area.size.height >= min_part * 2;
+        let wants_split = area.size.width > max_part || area.size.height > max_part;
+
+        if wants_split && (can_split_x || can_split_y) {
+            let split_x = if can_split_x && can_split_y {
+                area.size.width >= area.size.height
+            } else {
+                can_split_x
+            };
+
+            let (a, b) = if split_x {
+                let w = rng.gen_range(min_part, area.size.width - min_part + 1);
+                (Rect::new(area.origin, Size2D::new(w, area.size.height)),
+                 Rect::new(Point2D::new(area.origin.x + w, area.origin.y),
+                           Size2D::new(area.size.width - w, area.size.height)))
+            } else {
+                let h = rng.gen_range(min_part, area.size.height - min_part + 1);
+                (Rect::new(area.origin, Size2D::new(area.size.width, h)),
+                 Rect::new(Point2D::new(area.origin.x, area.origin.y + h),
+                           Size2D::new(area.size.width, area.size.height - h)))
+            };
+            self.split(rng, a, rooms);
+            self.split(rng, b, rooms);
+            return;
+        }
+
+        let space = Size2D::new(area.size.width - 2, area.size.height - 2);
+        if let Some(size) = self.room_size(rng, space) {
+            let x = area.origin.x + 1 + rng.gen_range(0, space.width - size.width + 1);
+            let y = area.origin.y + 1 + rng.gen_range(0, space.height - size.height + 1);
+            rooms.push(Rect::new(Point2D::new(x, y), size));
+        }
+    }
+
+    fn place_random<R: Rng>(&self, rng: &mut R, attempts: u32) -> Vec<Rect<i32>> {
+        let mut rooms: Vec<Rect<i32>> = Vec::new();
+        let space = Size2D::new(self.size.width as i32 - 2, self.size.height as i32 - 2);
+
+        for _ in 0..attempts {
+            if rooms.len() >= self.max_rooms {
+                break;
+            }
+            let size = match self.room_size(rng, space) {
+                Some(size) => size,
+                None => break,
+            };
+            let x = 1 + rng.gen_range(0, space.width - size.width + 1);
+            let y = 1 + rng.gen_range(0, space.height - size.height + 1);
+            let room = Rect::new(Point2D::new(x, y), size);
+
+            // Inflate to keep a wall between the rooms.
+            let bounds = room.inflate(1, 1);
+            if rooms.iter().all(|r| !r.intersects(&bounds)) {
+                rooms.push(room);
+            }
+        }
+
+        rooms
+    }
+
+    /// Dig a corridor between random points in two rooms.
+    ///
+    /// Shortest paths between two points stay inside the bounding box of the points in both
+    /// layouts, so the corridor never reaches the map edge.
+    fn dig<R: Rng>(&self,
+                   rng: &mut R,
+                   a: &Rect<i32>,
+                   b: &Rect<i32>,
+                   corridor: &mut HashSet<Point2D<i32>>)
+                   -> Result<(), DungeonError> {
+        let from = self.node(random_point(rng, a));
+        let to = self.node(random_point(rng, b));
+        let limit = max(1, self.size.width * self.size.height * 4);
+        let path = match astar_path_with(|a, b| a.distance(b), from, to, limit) {
+            Some(path) => path,
+            None => return Err(DungeonError::CorridorFailed),
+        };
+        for n in path {
+            corridor.insert(n.pos());
+        }
+        Ok(())
+    }
+}
+
+fn center(rect: &Rect<i32>) -> Point2D<i32> {
+    Point2D::new(rect.origin.x + rect.size.width / 2,
+                 rect.origin.y + rect.size.height / 2)
+}
+
+fn random_point<R: Rng>(rng: &mut R, rect: &Rect<i32>) -> Point2D<i32> {
+    Point2D::new(rng.gen_range(rect.min_x(), rect.max_x()),
+                 rng.gen_range(rect.min_y(), rect.max_y()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fmt;
+    use rand::{SeedableRng, XorShiftRng};
+    use euclid::Point2D;
+    use prefab_file::MapLayout;
+    use super::{Dungeon, DungeonGenerator, DungeonTerrain, RoomPlacement};
+
+    #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+    enum Terrain {
+        Wall,
+        Floor,
+        Corridor,
+    }
+
+    impl DungeonTerrain for Terrain {
+        fn wall() -> Terrain { Terrain::Wall }
+        fn floor() -> Terrain { Terrain::Floor }
+        fn corridor() -> Terrain { Terrain::Corridor }
+    }
+
+    impl fmt::Display for Terrain {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match *self {
+                Terrain::Wall => write!(f, "#"),
+                Terrain::Floor => write!(f, "."),
+                Terrain::Corridor => write!(f, ","),
+            }
+        }
+    }
+
+    fn check(gen: &DungeonGenerator) -> Dungeon<Terrain> {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let dungeon: Dungeon<Terrain> = gen.generate(&mut rng).unwrap();
+        assert_eq!(gen.size, dungeon.map.dim());
+        assert!(dungeon.rooms.len() > 1);
+        assert!(dungeon.is_connected(), "Disconnected dungeon\n{}", dungeon.map);
+
+        for (i, a) in dungeon.rooms.iter().enumerate() {
+            for b in &dungeon.rooms[i + 1..] {
+                assert!(!a.inflate(1, 1).intersects(b));
+            }
+            assert_eq!(Some(&Terrain::Floor), dungeon.map.get(a.origin));
+        }
+
+        // The map edge stays solid.
+        for (p, &t) in dungeon.map.iter() {
+            if p.x == 0 || p.y == 0 || p.x == gen.size.width as i32 - 1 ||
+               p.y == gen.size.height as i32 - 1 {
+                assert_eq!(Terrain::Wall, t);
+            }
+        }
+
+        // Same seed, same dungeon.
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        assert_eq!(Ok(dungeon.clone()), gen.generate(&mut rng));
+
+        dungeon
+    }
+
+    #[test]
+    fn test_bsp() {
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            check(&DungeonGenerator { layout: layout, ..Default::default() });
+        }
+    }
+
+    #[test]
+    fn test_bsp_max_rooms() {
+        let gen = DungeonGenerator { max_rooms: 3, ..Default::default() };
+        let all = DungeonGenerator { max_rooms: 100, ..Default::default() };
+        let mut first_rooms_only = true;
+        for seed in 1..10 {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 2, 3, 4]);
+            let dungeon: Dungeon<Terrain> = gen.generate(&mut rng).unwrap();
+            let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 2, 3, 4]);
+            let full: Dungeon<Terrain> = all.generate(&mut rng).unwrap();
+
+            assert_eq!(3, dungeon.rooms.len());
+            assert!(dungeon.rooms.iter().all(|r| full.rooms.contains(r)));
+            if dungeon.rooms[..] != full.rooms[..3] {
+                first_rooms_only = false;
+            }
+        }
+        // Rooms aren't just cut off from the end of the split.
+        assert!(!first_rooms_only);
+    }
+
+    #[test]
+    fn test_random_placement() {
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let dungeon = check(&DungeonGenerator {
+                layout: layout,
+                placement: RoomPlacement::Random { attempts: 200 },
+                max_rooms: 8,
+                ..Default::default()
+            });
+            assert!(dungeon.rooms.len() <= 8);
+        }
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let map = Dungeon {
+            map: vec![(Point2D::new(0, 0), Terrain::Floor),
+                      (Point2D::new(1, 0), Terrain::Wall),
+                      (Point2D::new(2, 0), Terrain::Corridor)]
+                     .into_iter()
+                     .collect(),
+            rooms: Vec::new(),
+            layout: MapLayout::Dense,
+        };
+        assert!(!map.is_connected());
+    }
+}
@@ -1,16 +1,46 @@
+extern crate base64;
 extern crate num;
 extern crate rand;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 extern crate euclid;
+extern crate flate2;
+extern crate calx_alg;
+extern crate calx_color;
+extern crate image;
+extern crate xml;
 
-pub use search::{Dijkstra, GridNode, astar_path_with};
+pub use cave::CaveGenerator;
+pub use dungeon::{Dungeon, DungeonError, DungeonGenerator, DungeonTerrain, RoomPlacement};
+pub use search::{Dijkstra, GridNode, MapNode, astar_path_with};
 pub use hex::{Dir12, Dir6, HexGeom};
 pub use hex_fov::{FovValue, HexFov};
+pub use maze::Maze;
+pub use pattern::{PatternCell, PatternMatch, PatternTransform, RewriteRule, find_matches};
 pub use prefab::{LegendBuilder, Prefab, PrefabIterator};
+pub use prefab_file::{MapLayout, PrefabError};
+pub use tiled::{StaggerAxis, StaggerIndex, TiledLayer, TiledMap, TiledObject, TiledObjectLayer,
+                TiledOrientation};
+pub use region::RegionMap;
+pub use rexpaint::{XpCell, char_to_cp437, cp437_to_char, load_xp, save_xp};
+pub use vault::{Vault, VaultPlacement, VaultPlacer};
+pub use wfc::{Wfc, WfcError};
 
+mod cave;
+mod dungeon;
 mod hex;
 mod hex_fov;
+mod maze;
+mod pattern;
 mod prefab;
+mod prefab_file;
+mod prefab_image;
+mod region;
+mod rexpaint;
 mod search;
+mod tiled;
+mod vault;
+mod wfc;
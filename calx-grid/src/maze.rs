@@ -0,0 +1,362 @@
+//! Maze generation on grid graphs.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::btree_set;
+use rand::Rng;
+use euclid::Point2D;
+use prefab::Prefab;
+use search::{GridNode, MapNode};
+
+/// A maze of cells with passages between neighboring cells.
+///
+/// Every pair of neighboring cells without a passage has a wall between them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Maze<N: Ord> {
+    cells: BTreeSet<N>,
+    /// Passages stored with the smaller node first.
+    passages: BTreeSet<(N, N)>,
+}
+
+impl<N: GridNode> Maze<N> {
+    /// Create a maze with walls between all the cells.
+    pub fn new<I: IntoIterator<Item = N>>(cells: I) -> Maze<N> {
+        Maze {
+            cells: cells.into_iter().collect(),
+            passages: BTreeSet::new(),
+        }
+    }
+
+    /// Generate a maze with the recursive backtracker algorithm.
+    ///
+    /// Makes long winding corridors with few branches. The maze algorithms only reach the cells
+    /// connected to a random starting cell, cells outside that area are left walled off.
+    pub fn backtracker<I, R>(rng: &mut R, cells: I) -> Maze<N>
+        where I: IntoIterator<Item = N>,
+              R: Rng
+    {
+        let mut ret = Maze::new(cells);
+        let start = match ret.random_cell(rng) {
+            Some(n) => n,
+            None => return ret,
+        };
+
+        let mut visited = BTreeSet::new();
+        visited.insert(start.clone());
+        let mut stack = vec![start];
+        while let Some(n) = stack.last().cloned() {
+            let next: Vec<N> = ret.neighbors(&n)
+                                  .into_iter()
+                                  .filter(|m| !visited.contains(m))
+                                  .collect();
+            if next.is_empty() {
+                stack.pop();
+                continue;
+            }
+            let m = rng.choose(&next).unwrap().clone();
+            ret.open(&n, &m);
+            visited.insert(m.clone());
+            stack.push(m);
+        }
+
+        ret
+    }
+
+    /// Generate a maze with the randomized Prim's algorithm.
+    ///
+    /// Makes many short branching dead ends.
+    pub fn prim<I, R>(rng: &mut R, cells: I) -> Maze<N>
+        where I: IntoIterator<Item = N>,
+              R: Rng
+    {
+        let mut ret = Maze::new(cells);
+        let start = match ret.random_cell(rng) {
+            Some(n) => n,
+            None => return ret,
+        };
+
+        let mut visited = BTreeSet::new();
+        let mut frontier = Vec::new();
+        visited.insert(start.clone());
+        for m in ret.neighbors(&start) {
+            frontier.push((start.clone(), m));
+        }
+
+        while !frontier.is_empty() {
+            let i = rng.gen_range(0, frontier.len());
+            let (n, m) = frontier.swap_remove(i);
+            if visited.contains(&m) {
+                continue;
+            }
+            ret.open(&n, &m);
+            visited.insert(m.clone());
+            for k in ret.neighbors(&m) {
+                if !visited.contains(&k) {
+                    frontier.push((m.clone(), k));
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// Generate a maze with Wilson's algorithm.
+    ///
+    /// Every possible maze is equally likely, so the maze has no bias towards any particular
+    /// shape.
+    pub fn wilson<I, R>(rng: &mut R, cells: I) -> Maze<N>
+        where I: IntoIterator<Item = N>,
+              R: Rng
+    {
+        let mut ret = Maze::new(cells);
+        let start = match ret.random_cell(rng) {
+            Some(n) => n,
+            None => return ret,
+        };
+
+        // Random walks from cells not connected to the start would never finish, so only walk
+        // in the connected area.
+        let mut area = BTreeSet::new();
+        area.insert(start.clone());
+        let mut edge = vec![start.clone()];
+        while let Some(n) = edge.pop() {
+            for m in ret.neighbors(&n) {
+                if area.insert(m.clone()) {
+                    edge.push(m);
+                }
+            }
+        }
+
+        let mut in_maze = BTreeSet::new();
+        in_maze.insert(start);
+        for n in &area {
+            if in_maze.contains(n) {
+                continue;
+            }
+
+            // Loop-erased random walk, remembering only the last exit from each cell.
+            let mut exits = HashMap::new();
+            let mut pos = n.clone();
+            while !in_maze.contains(&pos) {
+                let next = rng.choose(&ret.neighbors(&pos)).unwrap().clone();
+                exits.insert(pos, next.clone());
+                pos = next;
+            }
+
+            let mut pos = n.clone();
+            while !in_maze.contains(&pos) {
+                let next = exits[&pos].clone();
+                ret.open(&pos, &next);
+                in_maze.insert(pos);
+                pos = next;
+            }
+        }
+
+        ret
+    }
+
+    /// Remove dead ends by opening passages from them.
+    ///
+    /// Each dead end is removed with the probability `chance`. Passages are preferably opened
+    /// towards neighboring dead ends to remove two at once.
+    pub fn braid<R: Rng>(&mut self, rng: &mut R, chance: f32) {
+        let mut dead_ends = self.dead_ends();
+        rng.shuffle(&mut dead_ends);
+
+        for n in dead_ends {
+            if self.passages(&n).len() != 1 || rng.gen::<f32>() >= chance {
+                continue;
+            }
+
+            let walls: Vec<N> = self.neighbors(&n)
+                                    .into_iter()
+                                    .filter(|m| !self.is_open(&n, m))
+                                    .collect();
+            let best: Vec<N> = walls.iter()
+                                    .filter(|m| self.passages(m).len() == 1)
+                                    .cloned()
+                                    .collect();
+            let choice = if best.is_empty() { rng.choose(&walls) } else { rng.choose(&best) };
+            if let Some(m) = choice.cloned() {
+                self.open(&n, &m);
+            }
+        }
+    }
+
+    /// Return the cells of the maze.
+    pub fn cells<'a>(&'a self) -> btree_set::Iter<'a, N> { self.cells.iter() }
+
+    /// Return the passages of the maze.
+    pub fn edges<'a>(&'a self) -> btree_set::Iter<'a, (N, N)> { self.passages.iter() }
+
+    /// Return whether there is a passage between two cells.
+    pub fn is_open(&self, a: &N, b: &N) -> bool { self.passages.contains(&edge(a, b)) }
+
+    /// Open a passage between two cells.
+    pub fn open(&mut self, a: &N, b: &N) { self.passages.insert(edge(a, b)); }
+
+    /// Close the passage between two cells.
+    pub fn close(&mut self, a: &N, b: &N) { self.passages.remove(&edge(a, b)); }
+
+    /// Return the cells that have a passage from the given cell.
+    pub fn passages(&self, n: &N) -> Vec<N> {
+        n.neighbors().into_iter().filter(|m| self.is_open(n, m)).collect()
+    }
+
+    /// Return the cells with exactly one passage.
+    pub fn dead_ends(&self) -> Vec<N> {
+        self.cells.iter().filter(|n| self.passages(n).len() == 1).cloned().collect()
+    }
+
+    /// Return the neighbors of a cell that are in the maze.
+    fn neighbors(&self, n: &N) -> Vec<N> {
+        n.neighbors().into_iter().filter(|m| self.cells.contains(m)).collect()
+    }
+
+    fn random_cell<R: Rng>(&self, rng: &mut R) -> Option<N> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let i = rng.gen_range(0, self.cells.len());
+        self.cells.iter().nth(i).cloned()
+    }
+}
+
+impl Maze<MapNode> {
+    /// Render the maze as a prefab where open cells are `true`.
+    ///
+    /// The map has twice the resolution of the maze. Maze cells are at odd coordinates and the
+    /// cells between them are walls or passages. In the hex layout every map cell that is not a
+    /// maze cell lies on the edge between two maze cells. In the dense layout the map also has
+    /// corner cells that are always walls.
+    pub fn to_prefab(&self) -> Prefab<bool> {
+        if self.cells.is_empty() {
+            return Vec::new().into_iter().collect();
+        }
+
+        let project = |p: Point2D<i32>| Point2D::new(p.x * 2 + 1, p.y * 2 + 1);
+
+        let mut open = HashSet::new();
+        for n in &self.cells {
+            open.insert(project(n.pos()));
+        }
+        for &(ref a, ref b) in &self.passages {
+            open.insert(project(a.pos()) + (b.pos() - a.pos()));
+        }
+
+        let min_x = self.cells.iter().map(|n| n.x).min().unwrap();
+        let min_y = self.cells.iter().map(|n| n.y).min().unwrap();
+        let max_x = self.cells.iter().map(|n| n.x).max().unwrap();
+        let max_y = self.cells.iter().map(|n| n.y).max().unwrap();
+
+        let mut cells = Vec::new();
+        for y in (min_y * 2)..(max_y * 2 + 3) {
+            for x in (min_x * 2)..(max_x * 2 + 3) {
+                let p = Point2D::new(x, y);
+                cells.push((p, open.contains(&p)));
+            }
+        }
+        cells.into_iter().collect()
+    }
+}
+
+fn edge<N: GridNode>(a: &N, b: &N) -> (N, N) {
+    if a < b {
+        (a.clone(), b.clone())
+    } else {
+        (b.clone(), a.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, XorShiftRng};
+    use euclid::Point2D;
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use search::MapNode;
+    use super::Maze;
+
+    fn area(layout: MapLayout, w: i32, h: i32) -> Vec<MapNode> {
+        let mut ret = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                ret.push(MapNode::new(Point2D::new(x, y), layout));
+            }
+        }
+        ret
+    }
+
+    /// Check that the maze is a spanning tree of the area.
+    fn check_perfect(maze: &Maze<MapNode>) {
+        let cells: Vec<MapNode> = maze.cells().cloned().collect();
+        assert_eq!(cells.len() - 1, maze.edges().count());
+
+        let mut reached = vec![cells[0]];
+        let mut i = 0;
+        while i < reached.len() {
+            for n in maze.passages(&reached[i]) {
+                if !reached.contains(&n) {
+                    reached.push(n);
+                }
+            }
+            i += 1;
+        }
+        assert_eq!(cells.len(), reached.len());
+    }
+
+    #[test]
+    fn test_algorithms() {
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            check_perfect(&Maze::backtracker(&mut rng, area(layout, 12, 9)));
+            check_perfect(&Maze::prim(&mut rng, area(layout, 12, 9)));
+            check_perfect(&Maze::wilson(&mut rng, area(layout, 12, 9)));
+        }
+    }
+
+    #[test]
+    fn test_braid() {
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            let mut maze = Maze::backtracker(&mut rng, area(layout, 12, 9));
+            assert!(!maze.dead_ends().is_empty());
+            maze.braid(&mut rng, 1.0);
+            assert!(maze.dead_ends().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_disconnected() {
+        // Cells that can't be reached are left out.
+        let mut cells = area(MapLayout::Dense, 3, 3);
+        cells.push(MapNode::new(Point2D::new(10, 10), MapLayout::Dense));
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let maze = Maze::wilson(&mut rng, cells);
+        assert_eq!(8, maze.edges().count());
+    }
+
+    #[test]
+    fn test_prefab() {
+        let mut maze = Maze::new(area(MapLayout::Dense, 2, 2));
+        let n = |x, y| MapNode::new(Point2D::new(x, y), MapLayout::Dense);
+        maze.open(&n(0, 0), &n(1, 0));
+        maze.open(&n(1, 0), &n(1, 1));
+        maze.open(&n(1, 1), &n(0, 1));
+        assert_eq!(maze.to_prefab().map(|x| if x { '.' } else { '#' }),
+                   Prefab::from_text_map("
+#####
+#...#
+###.#
+#...#
+#####
+"));
+
+        // In the hex layout all non-cell map cells are edges.
+        let mut maze = Maze::new(area(MapLayout::Hex, 2, 1));
+        let n = |x, y| MapNode::new(Point2D::new(x, y), MapLayout::Hex);
+        maze.open(&n(0, 0), &n(1, 0));
+        let map = maze.to_prefab();
+        assert_eq!(3, map.iter().filter(|&(_, &x)| x).count());
+        assert_eq!(Some(&true), map.get(Point2D::new(2, 1)));
+    }
+}
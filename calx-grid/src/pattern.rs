@@ -0,0 +1,376 @@
+//! Finding patterns in prefabs and rewriting the matches.
+
+use std::hash::Hash;
+use euclid::Point2D;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+
+/// A cell of a pattern to match against a map.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PatternCell<T> {
+    /// Match anything, including empty cells.
+    Any,
+    /// Match an empty cell.
+    Empty,
+    /// Match a cell with the given value.
+    Is(T),
+    /// Match a cell with any of the given values.
+    OneOf(Vec<T>),
+}
+
+impl<T: PartialEq> PatternCell<T> {
+    /// Return whether the pattern cell matches a map cell.
+    pub fn matches(&self, cell: Option<&T>) -> bool {
+        match (self, cell) {
+            (&PatternCell::Any, _) => true,
+            (&PatternCell::Empty, None) => true,
+            (&PatternCell::Is(ref x), Some(y)) => x == y,
+            (&PatternCell::OneOf(ref xs), Some(y)) => xs.iter().any(|x| x == y),
+            _ => false,
+        }
+    }
+}
+
+/// A rotation and an optional mirroring applied to a pattern.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PatternTransform {
+    /// Number of clockwise rotation steps, 60 degrees for hex layouts and 90 for dense ones.
+    pub rotation: i32,
+    /// Whether the pattern is mirrored before the rotation.
+    pub mirror: bool,
+}
+
+impl PatternTransform {
+    /// Return every distinct transformation for a map layout, starting with the identity.
+    pub fn all(layout: MapLayout) -> Vec<PatternTransform> {
+        let steps = match layout {
+            MapLayout::Dense => 4,
+            MapLayout::Hex => 6,
+        };
+        let mut ret = Vec::new();
+        for &mirror in &[false, true] {
+            for rotation in 0..steps {
+                ret.push(PatternTransform {
+                    rotation: rotation,
+                    mirror: mirror,
+                });
+            }
+        }
+        ret
+    }
+
+    /// Apply the transformation to a prefab.
+    pub fn apply<U: Clone + Eq + Hash>(&self, prefab: &Prefab<U>, layout: MapLayout) -> Prefab<U> {
+        match layout {
+            MapLayout::Dense => {
+                if self.mirror {
+                    prefab.mirror_square().rotate_square(self.rotation)
+                } else {
+                    prefab.rotate_square(self.rotation)
+                }
+            }
+            MapLayout::Hex => {
+                if self.mirror {
+                    prefab.mirror_hex().rotate_hex(self.rotation)
+                } else {
+                    prefab.rotate_hex(self.rotation)
+                }
+            }
+        }
+    }
+}
+
+/// A location where a pattern matches a map.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PatternMatch {
+    /// Map position of the origin of the transformed pattern.
+    pub pos: Point2D<i32>,
+    /// Transformation of the pattern that matched.
+    pub transform: PatternTransform,
+}
+
+/// Find every place where a pattern matches a map under any rotation or mirroring.
+///
+/// Transformations that turn a symmetric pattern into a shape that was already tried are
+/// skipped, so each matching placement is reported only once. The matches are sorted by position.
+pub fn find_matches<T>(map: &Prefab<T>,
+                       pattern: &Prefab<PatternCell<T>>,
+                       layout: MapLayout)
+                       -> Vec<PatternMatch>
+    where T: Clone + Eq + Hash
+{
+    fn cell<T>(c: &PatternCell<T>) -> &PatternCell<T> { c }
+
+    find_transformed(map, pattern, layout, cell).into_iter().map(|(m, _)| m).collect()
+}
+
+fn find_transformed<T, U, F>(map: &Prefab<T>,
+                             pattern: &Prefab<U>,
+                             layout: MapLayout,
+                             cell: F)
+                             -> Vec<(PatternMatch, Prefab<U>)>
+    where T: Clone + Eq + Hash,
+          U: Clone + Eq + Hash,
+          F: Fn(&U) -> &PatternCell<T>
+{
+    let mut variants: Vec<(PatternTransform, Prefab<U>)> = Vec::new();
+    for t in PatternTransform::all(layout) {
+        let variant = t.apply(pattern, layout);
+        if !variants.iter().any(|&(_, ref v)| v == &variant) {
+            variants.push((t, variant));
+        }
+    }
+
+    let mut ret = Vec::new();
+    let map_dim = map.dim();
+    for &(t, ref variant) in &variants {
+        let dim = variant.dim();
+        // Patterns may hang over the map edges if they match empty cells there.
+        for y in (1 - dim.height as i32)..(map_dim.height as i32) {
+            for x in (1 - dim.width as i32)..(map_dim.width as i32) {
+                let pos = Point2D::new(x, y);
+                if variant.iter().all(|(p, c)| {
+                    cell(c).matches(map.get(Point2D::new(p.x + pos.x, p.y + pos.y)))
+                }) {
+                    ret.push((PatternMatch {
+                                  pos: pos,
+                                  transform: t,
+                              },
+                              variant.clone()));
+                }
+            }
+        }
+    }
+
+    ret.sort_by_key(|&(m, _)| (m.pos.y, m.pos.x));
+    ret
+}
+
+/// A rule that replaces the places where a pattern matches with new content.
+#[derive(Clone, Debug)]
+pub struct RewriteRule<T> {
+    /// The pattern and the replacement value of each cell.
+    ///
+    /// Cells with no replacement value are left as they are.
+    pub cells: Prefab<(PatternCell<T>, Option<T>)>,
+}
+
+impl<T: Clone + Eq + Hash> RewriteRule<T> {
+    pub fn new(cells: Prefab<(PatternCell<T>, Option<T>)>) -> RewriteRule<T> {
+        RewriteRule { cells: cells }
+    }
+
+    /// Build a rule from a pattern text map and a replacement text map drawn over the same area.
+    ///
+    /// Both maps are read with the same layout and stay aligned with each other. Whitespace in
+    /// the pattern matches anything and whitespace in the replacement leaves the cell unchanged.
+    pub fn from_text<F, G>(pattern: &str,
+                           replacement: &str,
+                           layout: MapLayout,
+                           pattern_legend: F,
+                           replacement_legend: G)
+                           -> RewriteRule<T>
+        where F: Fn(char) -> PatternCell<T>,
+              G: Fn(char) -> T
+    {
+        let mut cells = Vec::new();
+        let mut pattern_lines = pattern.lines();
+        let mut replacement_lines = replacement.lines();
+        let mut y = 0;
+        loop {
+            let (a, b) = match (pattern_lines.next(), replacement_lines.next()) {
+                (None, None) => break,
+                (a, b) => (a.unwrap_or(""), b.unwrap_or("")),
+            };
+
+            let mut a = a.chars();
+            let mut b = b.chars();
+            let mut x = 0;
+            loop {
+                let (p, r) = match (a.next(), b.next()) {
+                    (None, None) => break,
+                    (p, r) => (p.unwrap_or(' '), r.unwrap_or(' ')),
+                };
+
+                if !p.is_whitespace() || !r.is_whitespace() {
+                    let pos = match layout {
+                        MapLayout::Dense => Point2D::new(x, y),
+                        MapLayout::Hex => Point2D::new((x + y) / 2, y),
+                    };
+                    let p = if p.is_whitespace() { PatternCell::Any } else { pattern_legend(p) };
+                    let r = if r.is_whitespace() { None } else { Some(replacement_legend(r)) };
+                    cells.push((pos, (p, r)));
+                }
+                x += 1;
+            }
+            y += 1;
+        }
+
+        RewriteRule::new(cells.into_iter().collect())
+    }
+
+    /// Find the places where the rule matches a map.
+    pub fn find_matches(&self, map: &Prefab<T>, layout: MapLayout) -> Vec<PatternMatch> {
+        find_transformed(map, &self.cells, layout, rule_pattern)
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect()
+    }
+
+    /// Rewrite every match of the rule in the map.
+    ///
+    /// Matches are applied in position order. A match that would overwrite cells already written
+    /// by an earlier match is skipped. Returns the rewritten map and the matches that were
+    /// applied. Replacement cells outside the map extend it, and will move the map origin if they
+    /// fall above or left of it.
+    pub fn apply(&self, map: &Prefab<T>, layout: MapLayout) -> (Prefab<T>, Vec<PatternMatch>) {
+        use std::collections::{HashMap, HashSet};
+
+        let mut cells: HashMap<Point2D<i32>, T> =
+            map.iter().map(|(p, e)| (p, e.clone())).collect();
+        let mut written = HashSet::new();
+        let mut applied = Vec::new();
+
+        for (m, variant) in find_transformed(map, &self.cells, layout, rule_pattern) {
+            let mut targets = Vec::new();
+            for (p, &(_, ref r)) in variant.iter() {
+                if let Some(ref r) = *r {
+                    targets.push((Point2D::new(p.x + m.pos.x, p.y + m.pos.y), r.clone()));
+                }
+            }
+
+            if targets.iter().any(|&(p, _)| written.contains(&p)) {
+                continue;
+            }
+
+            for (p, r) in targets {
+                written.insert(p);
+                cells.insert(p, r);
+            }
+            applied.push(m);
+        }
+
+        (cells.into_iter().collect(), applied)
+    }
+}
+
+fn rule_pattern<T>(cell: &(PatternCell<T>, Option<T>)) -> &PatternCell<T> { &cell.0 }
+
+#[cfg(test)]
+mod test {
+    use euclid::Point2D;
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use super::{PatternCell, PatternTransform, RewriteRule, find_matches};
+
+    fn parse_pattern(c: char) -> PatternCell<char> {
+        match c {
+            '?' => PatternCell::Any,
+            '_' => PatternCell::Empty,
+            '*' => PatternCell::OneOf(vec!['#', '+']),
+            c => PatternCell::Is(c),
+        }
+    }
+
+    #[test]
+    fn test_pattern_cell() {
+        assert!(PatternCell::Any.matches(Some(&'a')));
+        assert!(PatternCell::<char>::Any.matches(None));
+        assert!(PatternCell::<char>::Empty.matches(None));
+        assert!(!PatternCell::Empty.matches(Some(&'a')));
+        assert!(PatternCell::Is('a').matches(Some(&'a')));
+        assert!(!PatternCell::Is('a').matches(Some(&'b')));
+        assert!(!PatternCell::Is('a').matches(None));
+        assert!(PatternCell::OneOf(vec!['a', 'b']).matches(Some(&'b')));
+        assert!(!PatternCell::OneOf(vec!['a', 'b']).matches(Some(&'c')));
+    }
+
+    #[test]
+    fn test_transforms() {
+        assert_eq!(8, PatternTransform::all(MapLayout::Dense).len());
+        assert_eq!(12, PatternTransform::all(MapLayout::Hex).len());
+    }
+
+    #[test]
+    fn test_find_rotated() {
+        let map = Prefab::from_text_map("
+#####
+#...#
+#.#.#
+#####
+");
+        let pattern = Prefab::from_text_map("
+#.
+#.
+").map(parse_pattern);
+
+        let matches = find_matches(&map, &pattern, MapLayout::Dense);
+        // The asymmetric pattern fits along every wall next to two floor cells.
+        assert!(matches.iter().any(|m| m.pos == Point2D::new(0, 1) && m.transform.rotation == 0));
+        for m in &matches {
+            let placed = m.transform.apply(&pattern, MapLayout::Dense);
+            for (p, c) in placed.iter() {
+                assert!(c.matches(map.get(Point2D::new(p.x + m.pos.x, p.y + m.pos.y))));
+            }
+        }
+
+        // A fully symmetric pattern only gets reported once per position.
+        let dot = Prefab::from_text_map("#").map(parse_pattern);
+        assert_eq!(map.iter().filter(|&(_, &c)| c == '#').count(),
+                   find_matches(&map, &dot, MapLayout::Dense).len());
+    }
+
+    #[test]
+    fn test_empty_cells() {
+        let map = Prefab::from_text_map("
+##
+");
+        // Wall cells with nothing above them.
+        let pattern = Prefab::from_text_map("
+_
+*
+").map(parse_pattern);
+
+        let matches = find_matches(&map, &pattern, MapLayout::Dense);
+        let found: Vec<_> = matches.iter()
+                                   .filter(|m| m.transform.rotation == 0 && !m.transform.mirror)
+                                   .map(|m| m.pos)
+                                   .collect();
+        assert_eq!(vec![Point2D::new(0, -1), Point2D::new(1, -1)], found);
+    }
+
+    #[test]
+    fn test_rewrite_dead_ends() {
+        let map = Prefab::from_text_hexmap("
+    # # # # # #
+   # . . . . #
+  # # # # # #
+");
+        // Turn the end of a dead-end corridor into a closet.
+        let rule = RewriteRule::from_text("
+    # #
+   # . .
+    # #
+",
+                                          "
+
+     +
+",
+                                          MapLayout::Hex,
+                                          parse_pattern,
+                                          |c| c);
+
+        let (result, applied) = rule.apply(&map, MapLayout::Hex);
+        assert_eq!(2, applied.len());
+        assert_eq!(result,
+                   Prefab::from_text_hexmap("
+    # # # # # #
+   # + . . + #
+  # # # # # #
+"));
+
+        // Nothing left to match.
+        let (_, applied) = rule.apply(&result, MapLayout::Hex);
+        assert!(applied.is_empty());
+    }
+}
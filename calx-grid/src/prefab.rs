@@ -5,13 +5,13 @@ use std::hash::Hash;
 use std::iter::{FromIterator, IntoIterator};
 use std::cmp::{max, min};
 use std::fmt;
-use euclid::{Point2D, Size2D};
+use euclid::{Point2D, Rect, Size2D};
 
 /// A structure for storing a piece of a grid map.
 ///
 /// All contents are guaranteed to be stored in the rectangle between (0, 0) (inclusive) and `dim`
 /// (exclusive).
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, Debug)]
 pub struct Prefab<T> {
     terrain: HashMap<Point2D<i32>, usize>,
     elements: Vec<T>,
@@ -27,6 +27,28 @@ impl<T: Clone + Eq + Hash> Prefab<T> {
         }
     }
 
+    /// Build a prefab from cells at their given positions.
+    ///
+    /// Unlike collecting into a prefab, this does not move the cells to the origin, so prefabs
+    /// built from parts of the same map stay lined up. Panics if a position is negative.
+    pub fn from_cells<I: IntoIterator<Item = (Point2D<i32>, T)>>(iter: I) -> Prefab<T> {
+        let mut element_idx: HashMap<T, usize> = HashMap::new();
+        let mut ret = Prefab::new();
+
+        for (p, e) in iter {
+            assert!(p.x >= 0 && p.y >= 0, "Negative prefab position {:?}", p);
+            let val = *element_idx.entry(e.clone()).or_insert_with(|| {
+                ret.elements.push(e);
+                ret.elements.len() - 1
+            });
+            ret.terrain.insert(p, val);
+            ret.dim = Size2D::new(max(ret.dim.width, p.x as u32 + 1),
+                                  max(ret.dim.height, p.y as u32 + 1));
+        }
+
+        ret
+    }
+
     pub fn get<'a>(&'a self, pos: Point2D<i32>) -> Option<&'a T> {
         self.terrain.get(&pos).map(|&idx| &self.elements[idx])
     }
@@ -47,8 +69,115 @@ impl<T: Clone + Eq + Hash> Prefab<T> {
             iter: self.terrain.iter(),
         }
     }
+
+    /// Return the number of cells with content in the prefab.
+    pub fn len(&self) -> usize { self.terrain.len() }
+
+    /// Return whether the prefab has no content.
+    pub fn is_empty(&self) -> bool { self.terrain.is_empty() }
+
+    /// Build a new prefab by moving every cell with a coordinate transformation.
+    ///
+    /// The result is normalized to the origin.
+    pub fn transform<F: Fn(Point2D<i32>) -> Point2D<i32>>(&self, f: F) -> Prefab<T> {
+        self.iter().map(|(p, e)| (f(p), e.clone())).collect()
+    }
+
+    /// Rotate a prefab in hex layout clockwise by `steps` 60 degree steps.
+    ///
+    /// Negative values rotate counterclockwise.
+    pub fn rotate_hex(&self, steps: i32) -> Prefab<T> {
+        let steps = (steps % 6 + 6) % 6;
+        self.transform(|mut p| {
+            for _ in 0..steps {
+                p = Point2D::new(p.x - p.y, p.x);
+            }
+            p
+        })
+    }
+
+    /// Mirror a prefab in hex layout along the vertical axis.
+    pub fn mirror_hex(&self) -> Prefab<T> { self.transform(|p| Point2D::new(p.y, p.x)) }
+
+    /// Rotate a prefab in dense layout clockwise by `steps` 90 degree steps.
+    ///
+    /// Negative values rotate counterclockwise.
+    pub fn rotate_square(&self, steps: i32) -> Prefab<T> {
+        let steps = (steps % 4 + 4) % 4;
+        self.transform(|mut p| {
+            for _ in 0..steps {
+                p = Point2D::new(-p.y, p.x);
+            }
+            p
+        })
+    }
+
+    /// Mirror a prefab in dense layout along the vertical axis.
+    pub fn mirror_square(&self) -> Prefab<T> { self.transform(|p| Point2D::new(-p.x, p.y)) }
+
+    /// Return the part of the prefab that lies inside the rectangle.
+    pub fn crop(&self, area: &Rect<i32>) -> Prefab<T> {
+        self.iter()
+            .filter(|&(p, _)| area.contains(&p))
+            .map(|(p, e)| (p, e.clone()))
+            .collect()
+    }
+
+    /// Stamp another prefab on top of this one with its origin at `offset`.
+    ///
+    /// Where both prefabs have content, the cell value is determined by calling `merge` with the
+    /// value of this prefab and the value of the stamped prefab.
+    pub fn blit<F>(&self, offset: Point2D<i32>, stamp: &Prefab<T>, mut merge: F) -> Prefab<T>
+        where F: FnMut(&T, &T) -> T
+    {
+        let mut cells: HashMap<Point2D<i32>, T> =
+            self.iter().map(|(p, e)| (p, e.clone())).collect();
+
+        for (p, e) in stamp.iter() {
+            let p = Point2D::new(p.x + offset.x, p.y + offset.y);
+            let value = match cells.get(&p) {
+                Some(old) => merge(old, e),
+                None => e.clone(),
+            };
+            cells.insert(p, value);
+        }
+
+        cells.into_iter().collect()
+    }
+
+    /// Return a prefab with the cells present in either prefab.
+    ///
+    /// Cells present in both prefabs are combined with `merge`.
+    pub fn union<F>(&self, other: &Prefab<T>, merge: F) -> Prefab<T>
+        where F: FnMut(&T, &T) -> T
+    {
+        self.blit(Point2D::new(0, 0), other, merge)
+    }
+
+    /// Return a prefab with the cells present in both prefabs.
+    ///
+    /// The values of the cells are combined with `merge`.
+    pub fn intersection<F>(&self, other: &Prefab<T>, mut merge: F) -> Prefab<T>
+        where F: FnMut(&T, &T) -> T
+    {
+        self.iter()
+            .filter_map(|(p, e)| other.get(p).map(|f| (p, merge(e, f))))
+            .collect()
+    }
+}
+
+impl<T: PartialEq> PartialEq for Prefab<T> {
+    fn eq(&self, other: &Prefab<T>) -> bool {
+        // Element indices depend on construction order, compare the actual values instead.
+        self.dim == other.dim && self.terrain.len() == other.terrain.len() &&
+        self.terrain.iter().all(|(p, &idx)| {
+            other.terrain.get(p).map_or(false, |&idx2| self.elements[idx] == other.elements[idx2])
+        })
+    }
 }
 
+impl<T: Eq> Eq for Prefab<T> {}
+
 pub struct PrefabIterator<'a, T: 'a> {
     prefab: &'a Prefab<T>,
     iter: hash_map::Iter<'a, Point2D<i32>, usize>,
@@ -90,6 +219,10 @@ impl<T: Clone + Eq + Hash> FromIterator<(Point2D<i32>, T)> for Prefab<T> {
             temp_buffer.push((p, val));
         }
 
+        if temp_buffer.is_empty() {
+            return ret;
+        }
+
         // Normalization: Snap bounding box to origin.
         let mut max_x = 0;
         let mut max_y = 0;
@@ -279,6 +412,115 @@ mod test {
         assert_eq!(Some(&'#'), a.get(Point2D::new(1, 2)));
     }
 
+    #[test]
+    fn test_rotate_hex() {
+        let map = Prefab::from_text_hexmap("
+    # # #
+   # . .
+  # # .
+ # . @
+");
+        let mut rotated = map.clone();
+        for _ in 0..6 {
+            assert_eq!(map.len(), rotated.len());
+            rotated = rotated.rotate_hex(1);
+        }
+        assert_eq!(map, rotated);
+        assert_eq!(map.rotate_hex(2), map.rotate_hex(-4));
+        assert_eq!(map.rotate_hex(3), map.rotate_hex(1).rotate_hex(1).rotate_hex(1));
+
+        let arrow = Prefab::from_text_hexmap("
+  a
+ b
+");
+        // Line along the southwest axis turns into a line along the northwest axis.
+        let turned = Prefab::from_text_hexmap("
+ b a
+");
+        assert_eq!(arrow.rotate_hex(1), turned);
+        assert_eq!(arrow.mirror_hex().mirror_hex(), arrow);
+    }
+
+    #[test]
+    fn test_rotate_square() {
+        let map = Prefab::from_text_map("
+ab
+c
+");
+        assert_eq!(map.rotate_square(1),
+                   Prefab::from_text_map("
+ca
+ b
+"));
+        assert_eq!(map.rotate_square(4), map);
+        assert_eq!(map.rotate_square(-1), map.rotate_square(3));
+        assert_eq!(map.mirror_square(),
+                   Prefab::from_text_map("
+ba
+ c
+"));
+    }
+
+    #[test]
+    fn test_crop_and_blit() {
+        use euclid::{Rect, Size2D};
+
+        let map = Prefab::from_text_map("
+###
+#..
+##.
+");
+        let cropped = map.crop(&Rect::new(Point2D::new(1, 1), Size2D::new(2, 2)));
+        assert_eq!(cropped, Prefab::from_text_map("
+..
+#.
+"));
+        assert_eq!(Size2D::new(2, 2), cropped.dim());
+        assert!(map.crop(&Rect::new(Point2D::new(5, 5), Size2D::new(2, 2))).is_empty());
+
+        let stamp = Prefab::from_text_map("
+@@
+");
+        let stamped = map.blit(Point2D::new(2, 2), &stamp, |_, &b| b);
+        assert_eq!(stamped,
+                   Prefab::from_text_map("
+###
+#..
+##@@
+"));
+
+        // Stamping out of bounds in negative direction normalizes the origin.
+        let stamped = map.blit(Point2D::new(-1, 0), &stamp, |&a, _| a);
+        assert_eq!(stamped,
+                   Prefab::from_text_map("
+@###
+ #..
+ ##.
+"));
+    }
+
+    #[test]
+    fn test_union_and_intersection() {
+        let a = Prefab::from_text_map("
+aa
+a
+");
+        let b = Prefab::from_text_map("
+ b
+bb
+");
+        assert_eq!(a.union(&b, |_, _| 'x'),
+                   Prefab::from_text_map("
+ax
+xb
+"));
+        assert_eq!(a.intersection(&b, |_, _| 'x'),
+                   Prefab::from_text_map("
+ x
+x
+"));
+    }
+
     /// Remove whitespace differences from text map strings.
     fn normalize(dirty: &str) -> String {
         // XXX: This should also normalize indetation by removing the longest whitespace prefix
@@ -0,0 +1,329 @@
+//! Text file format for prefabs with an embedded legend.
+//!
+//! A prefab file looks like this:
+//!
+//! ```notrust
+//! prefab hex
+//! legend
+//! # "Wall"
+//! . "Floor"
+//! map
+//!    # # #
+//!   # . .
+//!  # # .
+//! ```
+//!
+//! The header line names the layout of the map body, either `dense` or `hex`. The legend block
+//! maps each symbol to the JSON serialization of the value it stands for. Everything after the
+//! `map` line is the text map, in the format used by `Prefab::from_text_map` or
+//! `Prefab::from_text_hexmap`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use serde::{Deserialize, Serialize};
+use serde_json;
+use image;
+use calx_color::SRgba;
+use prefab::{LegendBuilder, Prefab};
+
+/// Layout of a text map.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub enum MapLayout {
+    /// Traditional text map where every character is a cell.
+    Dense,
+    /// Hex map where cells are separated by spaces and each row is shifted half a cell.
+    Hex,
+}
+
+/// Errors from reading and writing prefab files.
+#[derive(Debug)]
+pub enum PrefabError {
+    /// The legend alphabet has too few symbols for the distinct values in the prefab.
+    OutOfAlphabet {
+        /// Number of symbols the alphabet provided.
+        symbols: usize,
+        /// Number of distinct values that needed a symbol.
+        values: usize,
+    },
+    /// A map symbol has no entry in the legend.
+    UnknownSymbol(char),
+    /// An image pixel color has no entry in the legend.
+    UnknownColor(SRgba),
+    /// The file structure is malformed.
+    Syntax(String),
+    /// A legend value could not be serialized or deserialized.
+    Serde(serde_json::Error),
+    /// An image could not be decoded or encoded.
+    Image(image::ImageError),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrefabError::OutOfAlphabet { symbols, values } => {
+                write!(f,
+                       "Legend alphabet overflow: {} symbols for {} distinct values",
+                       symbols,
+                       values)
+            }
+            PrefabError::UnknownSymbol(c) => write!(f, "Map symbol {:?} not in legend", c),
+            PrefabError::UnknownColor(c) => write!(f, "Map color {} not in legend", c),
+            PrefabError::Syntax(ref s) => write!(f, "Bad prefab file: {}", s),
+            PrefabError::Serde(ref e) => write!(f, "Bad legend value: {}", e),
+            PrefabError::Image(ref e) => write!(f, "Bad image: {}", e),
+            PrefabError::Io(ref e) => e.fmt(f),
+        }
+    }
+}
+
+impl error::Error for PrefabError {
+    fn description(&self) -> &str {
+        match *self {
+            PrefabError::OutOfAlphabet { .. } => "legend alphabet overflow",
+            PrefabError::UnknownSymbol(_) => "unknown map symbol",
+            PrefabError::UnknownColor(_) => "unknown map color",
+            PrefabError::Syntax(_) => "bad prefab file",
+            PrefabError::Serde(_) => "bad legend value",
+            PrefabError::Image(_) => "bad image",
+            PrefabError::Io(_) => "prefab I/O error",
+        }
+    }
+}
+
+impl From<io::Error> for PrefabError {
+    fn from(e: io::Error) -> PrefabError { PrefabError::Io(e) }
+}
+
+impl From<serde_json::Error> for PrefabError {
+    fn from(e: serde_json::Error) -> PrefabError { PrefabError::Serde(e) }
+}
+
+impl From<image::ImageError> for PrefabError {
+    fn from(e: image::ImageError) -> PrefabError { PrefabError::Image(e) }
+}
+
+impl<T> Prefab<T>
+    where T: Clone + Eq + Hash + Ord + Serialize
+{
+    /// Write the prefab as a text file with a legend.
+    ///
+    /// Symbols for the values are assigned by the legend builder. Whitespace symbols are reserved
+    /// for empty cells and must not be in the builder's alphabet.
+    pub fn save_text<W, F>(&self,
+                           output: &mut W,
+                           layout: MapLayout,
+                           mut legend: LegendBuilder<T, F>)
+                           -> Result<(), PrefabError>
+        where W: Write,
+              F: FnMut(&T) -> &'static str
+    {
+        // Assign symbols in position order so that the output is stable.
+        let mut cells: Vec<_> = self.iter().collect();
+        cells.sort_by_key(|&(p, _)| (p.y, p.x));
+
+        let mut symbols = HashMap::new();
+        for &(_, e) in &cells {
+            if symbols.contains_key(e) {
+                continue;
+            }
+            match legend.add(e) {
+                Ok(c) => {
+                    symbols.insert(e.clone(), c);
+                }
+                Err(_) => {
+                    let values = cells.iter()
+                                      .map(|&(_, e)| e)
+                                      .collect::<BTreeSet<_>>()
+                                      .len();
+                    return Err(PrefabError::OutOfAlphabet {
+                        symbols: legend.legend.len(),
+                        values: values,
+                    });
+                }
+            }
+        }
+
+        let map: Prefab<char> = self.iter().map(|(p, e)| (p, symbols[e])).collect();
+
+        writeln!(output,
+                 "prefab {}",
+                 match layout {
+                     MapLayout::Dense => "dense",
+                     MapLayout::Hex => "hex",
+                 })?;
+        writeln!(output, "legend")?;
+        for (c, value) in &legend.legend {
+            writeln!(output, "{} {}", c, serde_json::to_string(value)?)?;
+        }
+        writeln!(output, "map")?;
+        match layout {
+            MapLayout::Dense => write!(output, "{}", map)?,
+            MapLayout::Hex => write!(output, "{}", map.hexmap_display())?,
+        }
+        Ok(())
+    }
+}
+
+impl<T> Prefab<T>
+    where T: Clone + Eq + Hash + Deserialize
+{
+    /// Read a prefab written with `save_text`.
+    ///
+    /// Returns the prefab and the layout its map was written in.
+    pub fn load_text<R: Read>(input: &mut R) -> Result<(Prefab<T>, MapLayout), PrefabError> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+
+        let mut lines = text.lines();
+
+        let layout = match lines.next().map(|s| s.trim()) {
+            Some("prefab dense") => MapLayout::Dense,
+            Some("prefab hex") => MapLayout::Hex,
+            Some(s) => return Err(PrefabError::Syntax(format!("Unknown header {:?}", s))),
+            None => return Err(PrefabError::Syntax("Empty file".to_string())),
+        };
+
+        if lines.next().map(|s| s.trim()) != Some("legend") {
+            return Err(PrefabError::Syntax("Missing legend block".to_string()));
+        }
+
+        let mut legend = BTreeMap::new();
+        loop {
+            let line = match lines.next() {
+                Some(line) => line,
+                None => return Err(PrefabError::Syntax("Missing map block".to_string())),
+            };
+            if line.trim() == "map" {
+                break;
+            }
+
+            let mut chars = line.chars();
+            let c = match chars.next() {
+                Some(c) if !c.is_whitespace() => c,
+                _ => return Err(PrefabError::Syntax(format!("Bad legend line {:?}", line))),
+            };
+            let value: T = serde_json::from_str(chars.as_str().trim())?;
+            if legend.insert(c, value).is_some() {
+                return Err(PrefabError::Syntax(format!("Duplicate legend symbol {:?}", c)));
+            }
+        }
+
+        let body = lines.collect::<Vec<_>>().join("\n");
+        let map = match layout {
+            MapLayout::Dense => Prefab::from_text_map(&body),
+            MapLayout::Hex => Prefab::from_text_hexmap(&body),
+        };
+
+        let mut cells = Vec::new();
+        for (p, c) in map.iter() {
+            match legend.get(c) {
+                Some(value) => cells.push((p, value.clone())),
+                None => return Err(PrefabError::UnknownSymbol(*c)),
+            }
+        }
+
+        Ok((cells.into_iter().collect(), layout))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use euclid::Point2D;
+    use prefab::{LegendBuilder, Prefab};
+    use super::{MapLayout, PrefabError};
+
+    #[derive(Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+    enum Terrain {
+        Wall,
+        Floor,
+        Door { locked: bool },
+    }
+
+    fn sample() -> Prefab<Terrain> {
+        Prefab::from_text_hexmap("
+    # # #
+   # . +
+  # # .
+ # . .
+")
+            .map(|c| match c {
+                '#' => Terrain::Wall,
+                '+' => Terrain::Door { locked: true },
+                _ => Terrain::Floor,
+            })
+    }
+
+    fn legend() -> LegendBuilder<Terrain, fn(&Terrain) -> &'static str> {
+        fn prefix(t: &Terrain) -> &'static str {
+            match *t {
+                Terrain::Wall => "#",
+                Terrain::Floor => ".",
+                _ => "",
+            }
+        }
+        LegendBuilder::new("abcdefghijklmnopqrstuvwxyz".to_string(), prefix)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let map = sample();
+
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let mut buf = Vec::new();
+            map.save_text(&mut buf, layout, legend()).unwrap();
+
+            let (map2, layout2) = Prefab::load_text(&mut &buf[..]).unwrap();
+            assert_eq!(layout, layout2);
+            assert_eq!(map, map2);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let mut buf = Vec::new();
+        sample().save_text(&mut buf, MapLayout::Dense, legend()).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        assert!(text.starts_with("prefab dense\nlegend\n"));
+        assert!(text.contains("# \"Wall\"\n"));
+        assert!(text.contains("a {\"Door\":{\"locked\":true}}\n"));
+        assert!(text.contains("map\n###\n#.a\n##.\n#..\n"));
+    }
+
+    #[test]
+    fn test_out_of_alphabet() {
+        fn no_prefix(_: &Terrain) -> &'static str { "" }
+        let mut buf = Vec::new();
+        let result = sample().save_text(&mut buf,
+                                        MapLayout::Dense,
+                                        LegendBuilder::new("xy".to_string(), no_prefix));
+        match result {
+            Err(PrefabError::OutOfAlphabet { symbols: 2, values: 3 }) => {}
+            x => panic!("Unexpected result {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let text = "prefab dense\nlegend\n# \"Wall\"\nmap\n#.\n";
+        match Prefab::<Terrain>::load_text(&mut text.as_bytes()) {
+            Err(PrefabError::UnknownSymbol('.')) => {}
+            x => panic!("Unexpected result {:?}", x),
+        }
+
+        let text = "prefab square\nlegend\nmap\n";
+        assert!(Prefab::<Terrain>::load_text(&mut text.as_bytes()).is_err());
+
+        let text = "prefab dense\nlegend\n# \"Wall\"\n";
+        assert!(Prefab::<Terrain>::load_text(&mut text.as_bytes()).is_err());
+
+        let text = "prefab dense\nlegend\n# \"Wall\"\nmap\n#\n";
+        let (map, _) = Prefab::<Terrain>::load_text(&mut text.as_bytes()).unwrap();
+        assert_eq!(Some(&Terrain::Wall), map.get(Point2D::new(0, 0)));
+    }
+}
@@ -0,0 +1,192 @@
+//! Reading and writing prefabs as PNG images.
+//!
+//! Every pixel color stands for a cell value through a color legend. Fully transparent pixels are
+//! empty cells.
+//!
+//! In the hex layout each cell is two pixels wide and every row is shifted one pixel to the left
+//! of the one above it, like the characters in a text hexmap. Cells are read from the pixels
+//! where x + y is even and the other pixels are ignored, so the images can be painted either with
+//! solid bricks or with one pixel cells and gaps between them. Saved images put the left pixel of
+//! each cell where x + y is even.
+
+use std::cmp::max;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{Read, Write};
+use euclid::Point2D;
+use image::{self, DynamicImage, ImageBuffer};
+use calx_color::SRgba;
+use prefab::Prefab;
+use prefab_file::{MapLayout, PrefabError};
+
+impl<T: Clone + Eq + Hash> Prefab<T> {
+    /// Read a prefab from a PNG image.
+    ///
+    /// Returns an error if the image has a visible color that is not in the legend.
+    pub fn load_png<R: Read>(input: &mut R,
+                             layout: MapLayout,
+                             legend: &HashMap<SRgba, T>)
+                             -> Result<Prefab<T>, PrefabError> {
+        let mut buf = Vec::new();
+        input.read_to_end(&mut buf)?;
+        let image = image::load_from_memory_with_format(&buf, image::PNG)?.to_rgba();
+
+        let mut cells = Vec::new();
+        let (width, height) = image.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                let pos = match layout {
+                    MapLayout::Dense => Point2D::new(x as i32, y as i32),
+                    MapLayout::Hex => {
+                        if (x + y) % 2 != 0 {
+                            continue;
+                        }
+                        Point2D::new(((x + y) / 2) as i32, y as i32)
+                    }
+                };
+
+                let color = SRgba::from(*image.get_pixel(x, y));
+                if color.a == 0 {
+                    continue;
+                }
+                match legend.get(&color) {
+                    Some(value) => cells.push((pos, value.clone())),
+                    None => return Err(PrefabError::UnknownColor(color)),
+                }
+            }
+        }
+
+        Ok(cells.into_iter().collect())
+    }
+
+    /// Write the prefab as a PNG image.
+    ///
+    /// The legend function gives the color for each cell value. Empty cells are left transparent.
+    pub fn save_png<W, F>(&self,
+                          output: &mut W,
+                          layout: MapLayout,
+                          legend: F)
+                          -> Result<(), PrefabError>
+        where W: Write,
+              F: Fn(&T) -> SRgba
+    {
+        // Left edge pixel positions of the cells.
+        let pixels: Vec<(Point2D<i32>, &T)> = match layout {
+            MapLayout::Dense => self.iter().collect(),
+            MapLayout::Hex => {
+                let pixels: Vec<_> = self.iter()
+                                         .map(|(p, e)| (Point2D::new(p.x * 2 - p.y, p.y), e))
+                                         .collect();
+                // Round down to even to keep the left pixels where x + y is even.
+                let min_x = pixels.iter().map(|&(p, _)| p.x).min().unwrap_or(0) & !1;
+                pixels.into_iter().map(|(p, e)| (Point2D::new(p.x - min_x, p.y), e)).collect()
+            }
+        };
+
+        let cell_width = match layout {
+            MapLayout::Dense => 1,
+            MapLayout::Hex => 2,
+        };
+
+        let width = pixels.iter().fold(1, |w, &(p, _)| max(w, p.x + cell_width));
+        let height = pixels.iter().fold(1, |h, &(p, _)| max(h, p.y + 1));
+
+        let mut image = ImageBuffer::from_pixel(width as u32,
+                                                height as u32,
+                                                SRgba::new(0, 0, 0, 0).into());
+        for &(p, e) in &pixels {
+            let color = legend(e);
+            for x in p.x..(p.x + cell_width) {
+                image.put_pixel(x as u32, p.y as u32, color.into());
+            }
+        }
+
+        DynamicImage::ImageRgba8(image).save(output, image::PNG)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use calx_color::SRgba;
+    use prefab::Prefab;
+    use prefab_file::{MapLayout, PrefabError};
+
+    fn color(c: char) -> SRgba {
+        match c {
+            '#' => SRgba::new(0, 0, 0, 255),
+            '.' => SRgba::new(255, 255, 255, 255),
+            _ => SRgba::new(255, 0, 0, 255),
+        }
+    }
+
+    fn legend() -> HashMap<SRgba, char> { "#.@".chars().map(|c| (color(c), c)).collect() }
+
+    #[test]
+    fn test_round_trip() {
+        let dense = Prefab::from_text_map("
+###
+#.@
+ #.
+");
+        let hex = Prefab::from_text_hexmap("
+    # # #
+   # . .
+  # # @
+ # . .
+");
+
+        for &(ref map, layout) in &[(dense, MapLayout::Dense), (hex, MapLayout::Hex)] {
+            let mut buf = Vec::new();
+            map.save_png(&mut buf, layout, |&c| color(c)).unwrap();
+            let map2 = Prefab::load_png(&mut &buf[..], layout, &legend()).unwrap();
+            assert_eq!(map, &map2);
+        }
+    }
+
+    #[test]
+    fn test_hex_image() {
+        let map = Prefab::from_text_hexmap("
+  # #
+ . @
+");
+        let mut buf = Vec::new();
+        map.save_png(&mut buf, MapLayout::Hex, |&c| color(c)).unwrap();
+
+        // Reading the image in dense layout shows the two pixel wide cells.
+        let dense = Prefab::load_png(&mut &buf[..], MapLayout::Dense, &legend()).unwrap();
+        assert_eq!(dense,
+                   Prefab::from_text_map("
+  ####
+ ..@@
+"));
+    }
+
+    #[test]
+    fn test_hex_gaps() {
+        // Cells painted one pixel wide on the even pixels.
+        let text = "
+# #
+ . @
+";
+        let mut buf = Vec::new();
+        Prefab::from_text_map(text).save_png(&mut buf, MapLayout::Dense, |&c| color(c)).unwrap();
+        let map = Prefab::load_png(&mut &buf[..], MapLayout::Hex, &legend()).unwrap();
+        assert_eq!(Prefab::from_text_hexmap(text), map);
+    }
+
+    #[test]
+    fn test_unknown_color() {
+        let map = Prefab::from_text_map("#x");
+        let mut buf = Vec::new();
+        map.save_png(&mut buf,
+                      MapLayout::Dense,
+                      |&c| if c == 'x' { SRgba::new(1, 2, 3, 255) } else { color(c) })
+           .unwrap();
+        match Prefab::load_png(&mut &buf[..], MapLayout::Dense, &legend()) {
+            Err(PrefabError::UnknownColor(c)) => assert_eq!(SRgba::new(1, 2, 3, 255), c),
+            x => panic!("Unexpected result {:?}", x),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Splitting open map space into rooms.
+
+use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use euclid::Point2D;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+use search::{GridNode, MapNode};
+
+/// Open map space split into regions, with the chokepoints that connect the regions.
+///
+/// The regions are found with a watershed on the distance transform of the open space. The
+/// distance of each cell from the nearest closed cell is computed, and every peak of the distance
+/// field grows into a region. Regions that meet at a saddle less than `min_depth` below the lower
+/// of their peaks are merged, so narrow doorways and corridors end up as the boundaries between
+/// regions while bumps in the walls of a large room don't split it.
+#[derive(Clone, Debug)]
+pub struct RegionMap {
+    regions: HashMap<Point2D<i32>, usize>,
+    cells: Vec<Vec<Point2D<i32>>>,
+    /// Cells on both sides of the border between each pair of adjacent regions.
+    chokepoints: BTreeMap<(usize, usize), Vec<Point2D<i32>>>,
+}
+
+impl RegionMap {
+    /// Split the passable cells of a prefab into regions.
+    pub fn new<T, F>(map: &Prefab<T>, layout: MapLayout, passable: F, min_depth: u32) -> RegionMap
+        where T: Clone + Eq + Hash,
+              F: Fn(&T) -> bool
+    {
+        RegionMap::from_cells(map.iter().filter(|&(_, e)| passable(e)).map(|(p, _)| p),
+                              layout,
+                              min_depth)
+    }
+
+    /// Split a set of open cells into regions.
+    pub fn from_cells<I>(cells: I, layout: MapLayout, min_depth: u32) -> RegionMap
+        where I: IntoIterator<Item = Point2D<i32>>
+    {
+        let open: HashSet<Point2D<i32>> = cells.into_iter().collect();
+        let neighbors = |p: Point2D<i32>| -> Vec<Point2D<i32>> {
+            MapNode::new(p, layout).neighbors().into_iter().map(|n| n.pos()).collect()
+        };
+
+        // Distance transform, grown from the open cells next to closed ones.
+        let mut dist = HashMap::new();
+        let mut edge = VecDeque::new();
+        for &p in &open {
+            if neighbors(p).iter().any(|q| !open.contains(q)) {
+                dist.insert(p, 1);
+                edge.push_back(p);
+            }
+        }
+        while let Some(p) = edge.pop_front() {
+            let d = dist[&p];
+            for q in neighbors(p) {
+                if open.contains(&q) && !dist.contains_key(&q) {
+                    dist.insert(q, d + 1);
+                    edge.push_back(q);
+                }
+            }
+        }
+
+        // Flood from the peaks down. A cell that touches no labeled cells starts a new basin,
+        // otherwise it joins the basin of its highest labeled neighbor. Where different basins
+        // touch, remember the highest point where they meet.
+        let mut order: Vec<Point2D<i32>> = open.iter().cloned().collect();
+        order.sort_by(|a, b| (dist[b], a.y, a.x).cmp(&(dist[a], b.y, b.x)));
+
+        let mut basin = HashMap::new();
+        let mut peaks = Vec::new();
+        let mut saddles: BTreeMap<(usize, usize), u32> = BTreeMap::new();
+        for &p in &order {
+            let mut labels: Vec<(u32, usize)> = neighbors(p)
+                                                    .into_iter()
+                                                    .filter_map(|q| {
+                                                        basin.get(&q).map(|&l| (dist[&q], l))
+                                                    })
+                                                    .collect();
+            // Highest neighbor first, lowest label on ties.
+            labels.sort_by(|a, b| (b.0, a.1).cmp(&(a.0, b.1)));
+
+            let label = match labels.first() {
+                Some(&(_, l)) => l,
+                None => {
+                    peaks.push(dist[&p]);
+                    peaks.len() - 1
+                }
+            };
+            basin.insert(p, label);
+
+            for &(_, l) in &labels {
+                if l != label {
+                    let key = (label.min(l), label.max(l));
+                    let saddle = saddles.entry(key).or_insert(0);
+                    *saddle = max(*saddle, dist[&p]);
+                }
+            }
+        }
+
+        // Merge shallow basins, highest saddles first.
+        let mut parent: Vec<usize> = (0..peaks.len()).collect();
+        let mut saddles: Vec<((usize, usize), u32)> = saddles.into_iter().collect();
+        saddles.sort_by(|a, b| b.1.cmp(&a.1));
+        for ((a, b), saddle) in saddles {
+            let (a, b) = (find(&mut parent, a), find(&mut parent, b));
+            if a == b {
+                continue;
+            }
+            if peaks[a].min(peaks[b]) - saddle < min_depth {
+                let (a, b) = (a.min(b), a.max(b));
+                parent[b] = a;
+                peaks[a] = max(peaks[a], peaks[b]);
+            }
+        }
+
+        // Number the regions in map order.
+        let mut ids = HashMap::new();
+        let mut regions = HashMap::new();
+        let mut region_cells: Vec<Vec<Point2D<i32>>> = Vec::new();
+        let mut cells: Vec<Point2D<i32>> = open.iter().cloned().collect();
+        cells.sort_by_key(|p| (p.y, p.x));
+        for &p in &cells {
+            let root = find(&mut parent, basin[&p]);
+            let id = *ids.entry(root).or_insert_with(|| {
+                region_cells.push(Vec::new());
+                region_cells.len() - 1
+            });
+            regions.insert(p, id);
+            region_cells[id].push(p);
+        }
+
+        let mut chokepoints: BTreeMap<(usize, usize), Vec<Point2D<i32>>> = BTreeMap::new();
+        for &p in &cells {
+            let a = regions[&p];
+            let mut others: Vec<usize> = neighbors(p)
+                                             .into_iter()
+                                             .filter_map(|q| regions.get(&q).cloned())
+                                             .filter(|&b| b != a)
+                                             .collect();
+            others.sort();
+            others.dedup();
+            for b in others {
+                chokepoints.entry((a.min(b), a.max(b))).or_insert_with(Vec::new).push(p);
+            }
+        }
+
+        RegionMap {
+            regions: regions,
+            cells: region_cells,
+            chokepoints: chokepoints,
+        }
+    }
+
+    /// Return the region of a cell, or `None` if the cell is not open.
+    pub fn region(&self, pos: Point2D<i32>) -> Option<usize> { self.regions.get(&pos).cloned() }
+
+    /// Return the number of regions.
+    ///
+    /// Regions are numbered from zero in the order of their topmost, leftmost cells.
+    pub fn region_count(&self) -> usize { self.cells.len() }
+
+    /// Return the cells of a region.
+    pub fn cells(&self, region: usize) -> &[Point2D<i32>] { &self.cells[region] }
+
+    /// Return the regions adjacent to a region.
+    pub fn neighbors(&self, region: usize) -> Vec<usize> {
+        self.chokepoints
+            .keys()
+            .filter_map(|&(a, b)| {
+                if a == region {
+                    Some(b)
+                } else if b == region {
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Return the cells on both sides of the border between two regions.
+    ///
+    /// The list is empty if the regions are not adjacent.
+    pub fn chokepoints(&self, a: usize, b: usize) -> &[Point2D<i32>] {
+        self.chokepoints.get(&(a.min(b), a.max(b))).map_or(&[], |v| &v[..])
+    }
+
+    /// Return every pair of adjacent regions, with the smaller region first.
+    pub fn connections(&self) -> Vec<(usize, usize)> { self.chokepoints.keys().cloned().collect() }
+}
+
+fn find(parent: &mut Vec<usize>, mut x: usize) -> usize {
+    while parent[x] != x {
+        parent[x] = parent[parent[x]];
+        x = parent[x];
+    }
+    x
+}
+
+#[cfg(test)]
+mod test {
+    use euclid::Point2D;
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use super::RegionMap;
+
+    #[test]
+    fn test_rooms() {
+        let map = Prefab::from_text_map("
+###########
+#...#.....#
+#.........#
+#...#.....#
+#####.#####
+#####.#####
+###.....###
+###.....###
+###.....###
+###########
+");
+        let regions = RegionMap::new(&map, MapLayout::Dense, |&c| c == '.', 1);
+        assert_eq!(3, regions.region_count());
+
+        let a = regions.region(Point2D::new(2, 2)).unwrap();
+        let b = regions.region(Point2D::new(7, 2)).unwrap();
+        let c = regions.region(Point2D::new(5, 7)).unwrap();
+        assert_eq!(None, regions.region(Point2D::new(0, 0)));
+        assert_eq!(Some(a), regions.region(Point2D::new(1, 3)));
+        assert_eq!(Some(b), regions.region(Point2D::new(9, 1)));
+
+        assert_eq!(vec![(a, b), (b, c)], regions.connections());
+        assert_eq!(vec![b], regions.neighbors(a));
+        assert!(regions.chokepoints(a, c).is_empty());
+        // The doorway cell is at the border.
+        assert!(regions.chokepoints(a, b).contains(&Point2D::new(4, 2)));
+        assert_eq!(2, regions.chokepoints(b, a).len());
+
+        // A large enough depth merges everything.
+        let regions = RegionMap::new(&map, MapLayout::Dense, |&c| c == '.', 10);
+        assert_eq!(1, regions.region_count());
+    }
+
+    #[test]
+    fn test_hex() {
+        let map = Prefab::from_text_hexmap("
+          # # # # # # # #
+         # . . . # . . . #
+        # . . . . . . . #
+       # . . . # . . . #
+      # # # # # # # # #
+
+      # # #
+     # . #
+    # # #
+");
+        let regions = RegionMap::new(&map, MapLayout::Hex, |&c| c == '.', 1);
+        assert_eq!(3, regions.region_count());
+        assert_eq!(1, regions.connections().len());
+        // The separate cell is a region of its own.
+        assert_eq!(1, regions.cells(2).len());
+        assert!(regions.neighbors(2).is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! Reading and writing REXPaint .xp image files.
+//!
+//! An .xp file is a gzip-compressed stack of equally sized layers. Each cell holds a code page 437
+//! glyph, a foreground color and a background color. Cells with the magenta background color
+//! `#FF00FF` are transparent in REXPaint and are left out of the prefabs.
+
+use std::cmp::max;
+use std::io::{self, Read, Write};
+use euclid::Point2D;
+use flate2;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use calx_color::SRgba;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+
+/// A REXPaint cell, glyph, foreground color and background color.
+pub type XpCell = (char, SRgba, SRgba);
+
+/// File format version written by REXPaint.
+const XP_VERSION: i32 = -1;
+
+/// Background color used for transparent cells.
+fn transparent() -> SRgba { SRgba::new(255, 0, 255, 255) }
+
+/// Read the layers of a REXPaint image.
+///
+/// All layers are moved by the same amount so that the topmost and leftmost visible cells of the
+/// image are at zero, the layers stay lined up with each other.
+///
+/// With `MapLayout::Hex` the image is read like a text hexmap, every other column of the image
+/// is expected to be blank and the layers are skewed into hex coordinates like in
+/// `Prefab::from_text_hexmap`. Blank glyphs are then treated as empty cells along with the
+/// transparent ones.
+pub fn load_xp<R: Read>(input: R, layout: MapLayout) -> io::Result<Vec<Prefab<XpCell>>> {
+    let mut input = GzDecoder::new(input)?;
+
+    // Old files start directly with the layer count, newer ones have a negative version number
+    // before it.
+    let mut n_layers = read_i32(&mut input)?;
+    if n_layers < 0 {
+        n_layers = read_i32(&mut input)?;
+    }
+    if n_layers < 0 {
+        return Err(invalid_data("Bad layer count"));
+    }
+
+    let mut layers = Vec::new();
+    for _ in 0..n_layers {
+        let width = read_i32(&mut input)?;
+        let height = read_i32(&mut input)?;
+        if width < 0 || height < 0 {
+            return Err(invalid_data("Bad layer size"));
+        }
+
+        let mut cells = Vec::new();
+        // Cells are stored in column-major order.
+        for x in 0..width {
+            for y in 0..height {
+                let glyph = match cp437_to_char(read_u32(&mut input)?) {
+                    Some(c) => c,
+                    None => return Err(invalid_data("Glyph out of code page 437 range")),
+                };
+                let mut rgb = [0u8; 6];
+                input.read_exact(&mut rgb)?;
+                let fore = SRgba::new(rgb[0], rgb[1], rgb[2], 255);
+                let back = SRgba::new(rgb[3], rgb[4], rgb[5], 255);
+
+                if back == transparent() {
+                    continue;
+                }
+
+                match layout {
+                    MapLayout::Dense => cells.push((Point2D::new(x, y), (glyph, fore, back))),
+                    MapLayout::Hex => {
+                        if glyph != '\0' && !glyph.is_whitespace() {
+                            cells.push((Point2D::new((x + y) / 2, y), (glyph, fore, back)));
+                        }
+                    }
+                }
+            }
+        }
+
+        layers.push(cells);
+    }
+
+    // Use one origin for all layers instead of normalizing each layer separately.
+    let min_x = layers.iter().flat_map(|l| l.iter()).map(|&(p, _)| p.x).min().unwrap_or(0);
+    let min_y = layers.iter().flat_map(|l| l.iter()).map(|&(p, _)| p.y).min().unwrap_or(0);
+    Ok(layers.into_iter()
+             .map(|l| {
+                 Prefab::from_cells(l.into_iter()
+                                     .map(|(p, c)| (Point2D::new(p.x - min_x, p.y - min_y), c)))
+             })
+             .collect())
+}
+
+/// Write prefabs as the layers of a REXPaint image.
+///
+/// The image is made large enough to fit every layer. With `MapLayout::Hex` the layers are
+/// drawn like `Prefab::hexmap_display` draws them. Glyphs that are not in code page 437 cause an
+/// `InvalidInput` error.
+pub fn save_xp<W: Write>(output: W,
+                         layers: &[Prefab<XpCell>],
+                         layout: MapLayout)
+                         -> io::Result<()> {
+    // Project the prefab coordinates into image coordinates.
+    let project = |p: Point2D<i32>| -> Point2D<i32> {
+        match layout {
+            MapLayout::Dense => p,
+            MapLayout::Hex => Point2D::new(p.x * 2 - p.y, p.y),
+        }
+    };
+
+    let min_x = layers.iter()
+                      .flat_map(|layer| layer.iter().map(|(p, _)| project(p).x))
+                      .min()
+                      .unwrap_or(0);
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut images = Vec::new();
+    for layer in layers {
+        let mut image = Vec::new();
+        for (p, &cell) in layer.iter() {
+            let mut p = project(p);
+            p.x -= min_x;
+            width = max(width, p.x + 1);
+            height = max(height, p.y + 1);
+            image.push((p, cell));
+        }
+        images.push(image);
+    }
+
+    let mut output = GzEncoder::new(output, flate2::Compression::Default);
+    write_i32(&mut output, XP_VERSION)?;
+    write_i32(&mut output, layers.len() as i32)?;
+
+    for image in &images {
+        let blank = ('\0', SRgba::new(0, 0, 0, 255), transparent());
+        let mut buf = vec![blank; (width * height) as usize];
+        for &(p, cell) in image {
+            // Column-major order.
+            buf[(p.x * height + p.y) as usize] = cell;
+        }
+
+        write_i32(&mut output, width)?;
+        write_i32(&mut output, height)?;
+        for &(glyph, fore, back) in &buf {
+            match char_to_cp437(glyph) {
+                Some(code) => write_u32(&mut output, code)?,
+                None => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              format!("Glyph {:?} not in code page 437", glyph)))
+                }
+            }
+            output.write_all(&[fore.r, fore.g, fore.b, back.r, back.g, back.b])?;
+        }
+    }
+
+    output.finish()?;
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg) }
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    input.read_exact(&mut buf)?;
+    Ok(buf[0] as u32 | (buf[1] as u32) << 8 | (buf[2] as u32) << 16 | (buf[3] as u32) << 24)
+}
+
+fn read_i32<R: Read>(input: &mut R) -> io::Result<i32> { read_u32(input).map(|x| x as i32) }
+
+fn write_u32<W: Write>(output: &mut W, x: u32) -> io::Result<()> {
+    output.write_all(&[x as u8, (x >> 8) as u8, (x >> 16) as u8, (x >> 24) as u8])
+}
+
+fn write_i32<W: Write>(output: &mut W, x: i32) -> io::Result<()> { write_u32(output, x as u32) }
+
+/// Code page 437 glyphs 0 to 31.
+static CP437_LOW: &'static str = "\0☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+/// Code page 437 glyphs 127 to 255.
+static CP437_HIGH: &'static str = "⌂ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗\
+                                   ╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Convert a code page 437 glyph code into an Unicode character.
+pub fn cp437_to_char(code: u32) -> Option<char> {
+    match code {
+        0...31 => CP437_LOW.chars().nth(code as usize),
+        32...126 => Some(code as u8 as char),
+        127...255 => CP437_HIGH.chars().nth(code as usize - 127),
+        _ => None,
+    }
+}
+
+/// Convert an Unicode character into a code page 437 glyph code.
+pub fn char_to_cp437(c: char) -> Option<u32> {
+    match c as u32 {
+        x @ 32...126 => Some(x),
+        _ => {
+            CP437_LOW.chars()
+                     .position(|x| x == c)
+                     .map(|i| i as u32)
+                     .or_else(|| CP437_HIGH.chars().position(|x| x == c).map(|i| i as u32 + 127))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use euclid::Point2D;
+    use calx_color::SRgba;
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use super::{XpCell, char_to_cp437, cp437_to_char, load_xp, save_xp};
+
+    #[test]
+    fn test_cp437() {
+        for i in 0..256 {
+            let c = cp437_to_char(i).unwrap();
+            assert_eq!(Some(i), char_to_cp437(c));
+        }
+        assert_eq!(None, cp437_to_char(256));
+        assert_eq!(Some('@'), cp437_to_char(64));
+        assert_eq!(Some('█'), cp437_to_char(219));
+        assert_eq!(None, char_to_cp437('λ'));
+    }
+
+    fn colorize(map: Prefab<char>) -> Prefab<XpCell> {
+        map.map(|c| (c, SRgba::new(c as u8, 0, 255, 255), SRgba::new(0, 0, c as u8, 255)))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let floor = colorize(Prefab::from_text_map("
+#####
+#...#
+#.▒.##
+#####
+"));
+        let items = colorize(Prefab::from_text_map("
+ @
+    ☺
+"));
+
+        let mut buf = Vec::new();
+        save_xp(&mut buf, &[floor.clone(), items.clone()], MapLayout::Dense).unwrap();
+        let layers = load_xp(&buf[..], MapLayout::Dense).unwrap();
+        assert_eq!(2, layers.len());
+        assert_eq!(floor, layers[0]);
+        assert_eq!(items, layers[1]);
+    }
+
+    #[test]
+    fn test_offset_layer() {
+        let floor = colorize(Prefab::from_text_map("
+#####
+#...#
+#####
+"));
+        // The second layer starts away from the origin.
+        let items = colorize(Prefab::from_cells(vec![(Point2D::new(3, 1), '@'),
+                                                     (Point2D::new(4, 2), '$')]));
+
+        let mut buf = Vec::new();
+        save_xp(&mut buf, &[floor.clone(), items.clone()], MapLayout::Dense).unwrap();
+        let layers = load_xp(&buf[..], MapLayout::Dense).unwrap();
+        assert_eq!(floor, layers[0]);
+        assert_eq!(items, layers[1]);
+        assert_eq!(Some('@'), layers[1].get(Point2D::new(3, 1)).map(|c| c.0));
+        assert_eq!(None, layers[1].get(Point2D::new(0, 0)));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let map = colorize(Prefab::from_text_hexmap("
+    # # #
+   # . .
+  # # .
+ # . .
+"));
+        let mut buf = Vec::new();
+        save_xp(&mut buf, &[map.clone()], MapLayout::Hex).unwrap();
+
+        let dense = load_xp(&buf[..], MapLayout::Dense).unwrap();
+        // Dense reading sees the map with the blank columns between hex cells.
+        assert_eq!(map.len(), dense[0].len());
+        assert!(dense[0].dim().width > map.dim().width);
+
+        let layers = load_xp(&buf[..], MapLayout::Hex).unwrap();
+        assert_eq!(map, layers[0]);
+    }
+
+    #[test]
+    fn test_bad_glyph() {
+        let map = colorize(Prefab::from_text_map("λ"));
+        assert!(save_xp(&mut Vec::new(), &[map], MapLayout::Dense).is_err());
+    }
+}
@@ -4,6 +4,9 @@ use std::collections::HashSet;
 use std::collections::BTreeMap;
 use num::{One, Zero};
 use num::traits::Num;
+use euclid::Point2D;
+use hex::{Dir6, HexGeom};
+use prefab_file::MapLayout;
 
 /// A node in a graph with a regular grid.
 pub trait GridNode: PartialEq + Eq + Clone + Hash + PartialOrd + Ord {
@@ -11,6 +14,55 @@ pub trait GridNode: PartialEq + Eq + Clone + Hash + PartialOrd + Ord {
     fn neighbors(&self) -> Vec<Self>;
 }
 
+/// A prefab map cell as a graph node.
+///
+/// In the dense layout the neighbors are the four orthogonally adjacent cells, in the hex layout
+/// they are the six adjacent hexes. The graph is unbounded, filter the neighbors against the map
+/// when needed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord, Debug)]
+pub struct MapNode {
+    pub x: i32,
+    pub y: i32,
+    pub layout: MapLayout,
+}
+
+impl MapNode {
+    pub fn new(pos: Point2D<i32>, layout: MapLayout) -> MapNode {
+        MapNode {
+            x: pos.x,
+            y: pos.y,
+            layout: layout,
+        }
+    }
+
+    pub fn pos(&self) -> Point2D<i32> { Point2D::new(self.x, self.y) }
+
+    /// Return the number of steps between two nodes.
+    pub fn distance(&self, other: &MapNode) -> i32 {
+        let d = other.pos() - self.pos();
+        match self.layout {
+            MapLayout::Dense => d.x.abs() + d.y.abs(),
+            MapLayout::Hex => d.hex_dist(),
+        }
+    }
+}
+
+impl GridNode for MapNode {
+    fn neighbors(&self) -> Vec<MapNode> {
+        match self.layout {
+            MapLayout::Dense => {
+                vec![MapNode::new(Point2D::new(self.x, self.y - 1), self.layout),
+                     MapNode::new(Point2D::new(self.x + 1, self.y), self.layout),
+                     MapNode::new(Point2D::new(self.x, self.y + 1), self.layout),
+                     MapNode::new(Point2D::new(self.x - 1, self.y), self.layout)]
+            }
+            MapLayout::Hex => {
+                Dir6::iter().map(|d| MapNode::new(self.pos() + d.to_v2(), self.layout)).collect()
+            }
+        }
+    }
+}
+
 /// A pathfinding map structure.
 ///
 /// A Dijkstra map lets you run pathfinding from any graph node it covers
@@ -58,6 +110,10 @@ impl<N: GridNode> Dijkstra<N> {
         Dijkstra { weights: weights }
     }
 
+    /// Return the distance of a node from the nearest goal, or `None` if the node is not covered
+    /// by the map.
+    pub fn distance(&self, node: &N) -> Option<u32> { self.weights.get(node).cloned() }
+
     /// Return the neighbors of a cell (if any), sorted from downhill to
     /// uphill.
     pub fn sorted_neighbors(&self, node: &N) -> Vec<N> {
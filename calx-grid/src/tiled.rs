@@ -0,0 +1,1022 @@
+//! Reading and writing Tiled map editor maps.
+//!
+//! Supports orthogonal and hexagonal maps in the JSON and TMX formats. Tile layers become prefabs
+//! of global tile ids and object layers become lists of objects with their properties.
+//!
+//! Hexagonal maps are converted from Tiled's staggered offset coordinates into the axial
+//! coordinates used everywhere else in the crate. A map staggered along the Y axis has the same
+//! shape as a text hexmap read with `Prefab::from_text_hexmap`. A map staggered along the X axis
+//! comes out mirrored along the diagonal, since the axial coordinates have no column-staggered
+//! layout.
+
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use base64;
+use euclid::{Point2D, Size2D};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde_json::{self, Value};
+use xml::reader::{EventReader, XmlEvent};
+use prefab::Prefab;
+use prefab_file::PrefabError;
+
+/// Tiled flags flipped tiles by setting the high bits of the global tile id.
+const GID_FLAG_MASK: u32 = 0xe000_0000;
+
+/// Which axis a hexagonal Tiled map is staggered along.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StaggerAxis {
+    /// Every other column is shifted down, hexes have flat tops.
+    X,
+    /// Every other row is shifted right, hexes have pointy tops.
+    Y,
+}
+
+/// Whether the odd or the even rows or columns of a hexagonal Tiled map are shifted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StaggerIndex {
+    Odd,
+    Even,
+}
+
+/// Tiled map orientation.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TiledOrientation {
+    Orthogonal,
+    Hexagonal {
+        stagger_axis: StaggerAxis,
+        stagger_index: StaggerIndex,
+        /// Length of the hex side in pixels.
+        side_length: u32,
+    },
+}
+
+impl TiledOrientation {
+    /// Convert Tiled column and row coordinates into map coordinates.
+    ///
+    /// Orthogonal coordinates stay as they are, hexagonal ones are converted to axial
+    /// coordinates.
+    pub fn to_axial(&self, offset: Point2D<i32>) -> Point2D<i32> {
+        match *self {
+            TiledOrientation::Orthogonal => offset,
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::Y, stagger_index, .. } => {
+                Point2D::new(offset.x + stagger_shift(offset.y, stagger_index), offset.y)
+            }
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::X, stagger_index, .. } => {
+                Point2D::new(offset.y + stagger_shift(offset.x, stagger_index), offset.x)
+            }
+        }
+    }
+
+    /// Convert map coordinates into Tiled column and row coordinates.
+    pub fn from_axial(&self, pos: Point2D<i32>) -> Point2D<i32> {
+        match *self {
+            TiledOrientation::Orthogonal => pos,
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::Y, stagger_index, .. } => {
+                Point2D::new(pos.x - stagger_shift(pos.y, stagger_index), pos.y)
+            }
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::X, stagger_index, .. } => {
+                Point2D::new(pos.y, pos.x - stagger_shift(pos.y, stagger_index))
+            }
+        }
+    }
+
+    /// Find the Tiled column and row of the tile a pixel position falls on.
+    ///
+    /// Hex tiles are approximated with rectangles, so positions near the slanted edges may land
+    /// on the neighboring tile.
+    fn pixel_to_offset(&self, tile_size: Size2D<u32>, pos: Point2D<f32>) -> Point2D<i32> {
+        let (w, h) = (tile_size.width as f32, tile_size.height as f32);
+        match *self {
+            TiledOrientation::Orthogonal => {
+                Point2D::new((pos.x / w).floor() as i32, (pos.y / h).floor() as i32)
+            }
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::Y,
+                                          stagger_index,
+                                          side_length } => {
+                let row = (pos.y / ((h + side_length as f32) / 2.0)).floor() as i32;
+                let x = if is_staggered(row, stagger_index) { pos.x - w / 2.0 } else { pos.x };
+                Point2D::new((x / w).floor() as i32, row)
+            }
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::X,
+                                          stagger_index,
+                                          side_length } => {
+                let col = (pos.x / ((w + side_length as f32) / 2.0)).floor() as i32;
+                let y = if is_staggered(col, stagger_index) { pos.y - h / 2.0 } else { pos.y };
+                Point2D::new(col, (y / h).floor() as i32)
+            }
+        }
+    }
+}
+
+fn is_staggered(n: i32, index: StaggerIndex) -> bool {
+    (n & 1 == 1) == (index == StaggerIndex::Odd)
+}
+
+/// How much a staggered row or column shifts the axial coordinate.
+fn stagger_shift(n: i32, index: StaggerIndex) -> i32 {
+    // n +/- (n & 1) is always even, also for negative numbers, so the division is exact.
+    match index {
+        StaggerIndex::Odd => (n + (n & 1)) / 2,
+        StaggerIndex::Even => (n - (n & 1)) / 2,
+    }
+}
+
+/// A tile layer of a Tiled map.
+#[derive(Clone, Debug)]
+pub struct TiledLayer {
+    pub name: String,
+    /// Map coordinates of the origin of the tiles prefab.
+    pub origin: Point2D<i32>,
+    /// Global tile ids of the layer, including the flip flags in the high bits.
+    ///
+    /// Empty cells are left out.
+    pub tiles: Prefab<u32>,
+}
+
+/// An object from an object layer of a Tiled map.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TiledObject {
+    pub name: String,
+    /// The type field of the object.
+    pub kind: String,
+    /// Position of the object in pixels.
+    pub pixel_pos: Point2D<f32>,
+    /// Map coordinates of the tile the object is on.
+    pub pos: Point2D<i32>,
+    /// Object properties, all values are converted to strings.
+    pub properties: BTreeMap<String, String>,
+}
+
+/// An object layer of a Tiled map.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TiledObjectLayer {
+    pub name: String,
+    pub objects: Vec<TiledObject>,
+}
+
+/// A map from the Tiled map editor.
+#[derive(Clone, Debug)]
+pub struct TiledMap {
+    pub orientation: TiledOrientation,
+    /// Size of a tile in pixels.
+    pub tile_size: Size2D<u32>,
+    /// First global id and source file for each external tileset of the map.
+    ///
+    /// Tilesets embedded in the map file are not kept.
+    pub tilesets: Vec<(u32, String)>,
+    pub layers: Vec<TiledLayer>,
+    pub object_layers: Vec<TiledObjectLayer>,
+}
+
+impl TiledMap {
+    pub fn new(orientation: TiledOrientation, tile_size: Size2D<u32>) -> TiledMap {
+        TiledMap {
+            orientation: orientation,
+            tile_size: tile_size,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            object_layers: Vec::new(),
+        }
+    }
+
+    /// Add a tile layer, placed so that it starts from the top left corner of the Tiled map.
+    pub fn push_layer(&mut self, name: &str, tiles: Prefab<u32>) {
+        let offsets: Vec<Point2D<i32>> =
+            tiles.iter().map(|(p, _)| self.orientation.from_axial(p)).collect();
+        // Moving along the axial x axis only moves the column of a row-staggered map and the row
+        // of a column-staggered map.
+        let min_offset = match self.orientation {
+            TiledOrientation::Orthogonal |
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::Y, .. } => {
+                offsets.iter().map(|p| p.x).min()
+            }
+            TiledOrientation::Hexagonal { stagger_axis: StaggerAxis::X, .. } => {
+                offsets.iter().map(|p| p.y).min()
+            }
+        };
+        let origin = Point2D::new(max(0, -min_offset.unwrap_or(0)), 0);
+
+        self.layers.push(TiledLayer {
+            name: name.to_string(),
+            origin: origin,
+            tiles: tiles,
+        });
+    }
+
+    /// Read a map in Tiled's JSON format.
+    pub fn load_json<R: Read>(input: R) -> Result<TiledMap, PrefabError> {
+        let json: Value = serde_json::from_reader(input)?;
+
+        if json.get("infinite").and_then(|x| x.as_bool()) == Some(true) {
+            return Err(bad("Infinite maps are not supported"));
+        }
+
+        let orientation = parse_orientation(json_str(&json, "orientation"),
+                                            json_str(&json, "staggeraxis"),
+                                            json_str(&json, "staggerindex"),
+                                            json_u32(&json, "hexsidelength"))?;
+        let mut map = TiledMap::new(orientation,
+                                    Size2D::new(json_u32(&json, "tilewidth").unwrap_or(1),
+                                                json_u32(&json, "tileheight").unwrap_or(1)));
+
+        if let Some(tilesets) = json.get("tilesets").and_then(|x| x.as_array()) {
+            for tileset in tilesets {
+                if let (Some(first_gid), Some(source)) = (json_u32(tileset, "firstgid"),
+                                                          json_str(tileset, "source")) {
+                    map.tilesets.push((first_gid, source.to_string()));
+                }
+            }
+        }
+
+        if let Some(layers) = json.get("layers") {
+            map.load_json_layers(layers)?;
+        }
+
+        Ok(map)
+    }
+
+    fn load_json_layers(&mut self, layers: &Value) -> Result<(), PrefabError> {
+        let layers = match layers.as_array() {
+            Some(layers) => layers,
+            None => return Err(bad("Layers is not an array")),
+        };
+
+        for layer in layers {
+            let name = json_str(layer, "name").unwrap_or("").to_string();
+            match json_str(layer, "type") {
+                Some("tilelayer") => {
+                    let width = json_u32(layer, "width").unwrap_or(0);
+                    let gids = match layer.get("data") {
+                        Some(&Value::Array(ref data)) => {
+                            let mut gids = Vec::new();
+                            for x in data {
+                                match x.as_u64() {
+                                    Some(gid) => gids.push(gid as u32),
+                                    None => return Err(bad("Bad tile id")),
+                                }
+                            }
+                            gids
+                        }
+                        Some(&Value::String(ref data)) => {
+                            if json_str(layer, "encoding") != Some("base64") {
+                                return Err(bad("Unknown layer encoding"));
+                            }
+                            decode_base64(data, json_str(layer, "compression").unwrap_or(""))?
+                        }
+                        _ => return Err(bad("Tile layer without data")),
+                    };
+                    let layer = self.build_layer(name, width, &gids)?;
+                    self.layers.push(layer);
+                }
+                Some("objectgroup") => {
+                    let mut objects = Vec::new();
+                    if let Some(data) = layer.get("objects").and_then(|x| x.as_array()) {
+                        for obj in data {
+                            let mut properties = BTreeMap::new();
+                            match obj.get("properties") {
+                                // Tiled 1.2 and later write a list of name, type and value.
+                                Some(&Value::Array(ref props)) => {
+                                    for prop in props {
+                                        if let (Some(name), Some(value)) = (json_str(prop, "name"),
+                                                                            prop.get("value")) {
+                                            properties.insert(name.to_string(),
+                                                              json_to_string(value));
+                                        }
+                                    }
+                                }
+                                // Older versions write an object.
+                                Some(&Value::Object(ref props)) => {
+                                    for (name, value) in props {
+                                        properties.insert(name.clone(), json_to_string(value));
+                                    }
+                                }
+                                _ => {}
+                            }
+
+                            let pixel_pos = Point2D::new(json_f32(obj, "x"), json_f32(obj, "y"));
+                            objects.push(self.build_object(json_str(obj, "name").unwrap_or(""),
+                                                           json_str(obj, "type").unwrap_or(""),
+                                                           pixel_pos,
+                                                           properties));
+                        }
+                    }
+                    self.object_layers.push(TiledObjectLayer {
+                        name: name,
+                        objects: objects,
+                    });
+                }
+                Some("group") => {
+                    if let Some(sublayers) = layer.get("layers") {
+                        self.load_json_layers(sublayers)?;
+                    }
+                }
+                // Skip image layers.
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a map in Tiled's TMX format.
+    pub fn load_tmx<R: Read>(input: R) -> Result<TiledMap, PrefabError> {
+        let mut map = None;
+        // Element names from the root to the current element.
+        let mut path: Vec<String> = Vec::new();
+
+        // State of the current tile layer.
+        let mut layer_name = String::new();
+        let mut layer_width = 0;
+        let mut encoding = String::new();
+        let mut compression = String::new();
+        let mut text = String::new();
+        let mut gids = Vec::new();
+
+        // State of the current object layer and object.
+        let mut object_layer = None;
+        let mut object = None;
+
+        for event in EventReader::new(input) {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => return Err(PrefabError::Syntax(format!("{}", e))),
+            };
+
+            match event {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let name = name.local_name;
+                    let attr = |key: &str| -> Option<&str> {
+                        attributes.iter()
+                                  .find(|a| a.name.local_name == key)
+                                  .map(|a| &a.value[..])
+                    };
+                    let attr_u32 = |key: &str| attr(key).and_then(|x| x.parse::<u32>().ok());
+                    let attr_f32 =
+                        |key: &str| attr(key).and_then(|x| x.parse::<f32>().ok()).unwrap_or(0.0);
+
+                    let parent = path.last().map_or("", |x| &x[..]);
+                    if name != "map" && map.is_none() {
+                        return Err(bad("Root element is not map"));
+                    }
+
+                    match (parent, &name[..]) {
+                        ("", "map") => {
+                            if attr("infinite") == Some("1") {
+                                return Err(bad("Infinite maps are not supported"));
+                            }
+                            let orientation = parse_orientation(attr("orientation"),
+                                                                attr("staggeraxis"),
+                                                                attr("staggerindex"),
+                                                                attr_u32("hexsidelength"))?;
+                            map = Some(TiledMap::new(orientation,
+                                                     Size2D::new(attr_u32("tilewidth")
+                                                                     .unwrap_or(1),
+                                                                 attr_u32("tileheight")
+                                                                     .unwrap_or(1))));
+                        }
+                        ("map", "tileset") => {
+                            if let (Some(first_gid), Some(source)) = (attr_u32("firstgid"),
+                                                                      attr("source")) {
+                                let source = source.to_string();
+                                map.as_mut().unwrap().tilesets.push((first_gid, source));
+                            }
+                        }
+                        (_, "layer") => {
+                            layer_name = attr("name").unwrap_or("").to_string();
+                            layer_width = attr_u32("width").unwrap_or(0);
+                            gids.clear();
+                        }
+                        ("layer", "data") => {
+                            encoding = attr("encoding").unwrap_or("").to_string();
+                            compression = attr("compression").unwrap_or("").to_string();
+                            text.clear();
+                        }
+                        ("data", "chunk") => {
+                            return Err(bad("Infinite maps are not supported"));
+                        }
+                        ("data", "tile") => gids.push(attr_u32("gid").unwrap_or(0)),
+                        (_, "objectgroup") => {
+                            object_layer = Some(TiledObjectLayer {
+                                name: attr("name").unwrap_or("").to_string(),
+                                objects: Vec::new(),
+                            });
+                        }
+                        ("objectgroup", "object") => {
+                            let pixel_pos = Point2D::new(attr_f32("x"), attr_f32("y"));
+                            object = Some(map.as_ref().unwrap().build_object(attr("name")
+                                                                                 .unwrap_or(""),
+                                                                             attr("type")
+                                                                                 .unwrap_or(""),
+                                                                             pixel_pos,
+                                                                             BTreeMap::new()));
+                        }
+                        ("properties", "property") => {
+                            if path.len() >= 2 && path[path.len() - 2] == "object" {
+                                if let (Some(obj), Some(key)) = (object.as_mut(), attr("name")) {
+                                    obj.properties.insert(key.to_string(),
+                                                          attr("value").unwrap_or("").to_string());
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+
+                    path.push(name);
+                }
+                XmlEvent::Characters(s) => {
+                    if path.last().map_or(false, |x| x == "data") {
+                        text.push_str(&s);
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    let name = path.pop().unwrap_or(String::new());
+                    let parent = path.last().map_or("", |x| &x[..]);
+                    match (parent, &name[..]) {
+                        ("layer", "data") => {
+                            match &encoding[..] {
+                                "csv" => {
+                                    for s in text.split(',') {
+                                        match s.trim().parse::<u32>() {
+                                            Ok(gid) => gids.push(gid),
+                                            Err(_) => return Err(bad("Bad tile id")),
+                                        }
+                                    }
+                                }
+                                "base64" => gids = decode_base64(&text, &compression)?,
+                                // XML tile elements, already collected.
+                                "" => {}
+                                _ => return Err(bad("Unknown layer encoding")),
+                            }
+                        }
+                        (_, "layer") => {
+                            let map = map.as_mut().unwrap();
+                            let layer = map.build_layer(layer_name.clone(), layer_width, &gids)?;
+                            map.layers.push(layer);
+                        }
+                        ("objectgroup", "object") => {
+                            if let (Some(layer), Some(obj)) = (object_layer.as_mut(),
+                                                               object.take()) {
+                                layer.objects.push(obj);
+                            }
+                        }
+                        (_, "objectgroup") => {
+                            if let Some(layer) = object_layer.take() {
+                                map.as_mut().unwrap().object_layers.push(layer);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match map {
+            Some(map) => Ok(map),
+            None => Err(bad("No map element")),
+        }
+    }
+
+    /// Write the map in Tiled's JSON format.
+    pub fn save_json<W: Write>(&self, mut output: W) -> Result<(), PrefabError> {
+        let (size, grids) = self.layer_grids()?;
+
+        let mut json = json!({
+            "type": "map",
+            "version": 1,
+            "orientation": "orthogonal",
+            "renderorder": "right-down",
+            "width": size.width,
+            "height": size.height,
+            "tilewidth": self.tile_size.width,
+            "tileheight": self.tile_size.height,
+            "infinite": false,
+            "nextobjectid": self.object_layers.iter().map(|x| x.objects.len()).sum::<usize>() + 1,
+        });
+
+        if let TiledOrientation::Hexagonal { stagger_axis, stagger_index, side_length } =
+               self.orientation {
+            let obj = json.as_object_mut().unwrap();
+            obj.insert("orientation".to_string(), json!("hexagonal"));
+            obj.insert("staggeraxis".to_string(), json!(axis_name(stagger_axis)));
+            obj.insert("staggerindex".to_string(), json!(index_name(stagger_index)));
+            obj.insert("hexsidelength".to_string(), json!(side_length));
+        }
+
+        let tilesets: Vec<Value> = self.tilesets
+                                       .iter()
+                                       .map(|&(first_gid, ref source)| {
+                                           json!({"firstgid": first_gid, "source": source})
+                                       })
+                                       .collect();
+
+        let mut layers = Vec::new();
+        for (layer, gids) in self.layers.iter().zip(grids) {
+            layers.push(json!({
+                "type": "tilelayer",
+                "name": layer.name,
+                "x": 0,
+                "y": 0,
+                "width": size.width,
+                "height": size.height,
+                "opacity": 1,
+                "visible": true,
+                "data": gids,
+            }));
+        }
+
+        let mut id = 1;
+        for layer in &self.object_layers {
+            let mut objects = Vec::new();
+            for obj in &layer.objects {
+                let properties: Vec<Value> = obj.properties
+                                                .iter()
+                                                .map(|(k, v)| {
+                                                    json!({"name": k, "type": "string", "value": v})
+                                                })
+                                                .collect();
+                objects.push(json!({
+                    "id": id,
+                    "name": obj.name,
+                    "type": obj.kind,
+                    "x": obj.pixel_pos.x,
+                    "y": obj.pixel_pos.y,
+                    "width": 0,
+                    "height": 0,
+                    "rotation": 0,
+                    "visible": true,
+                    "properties": properties,
+                }));
+                id += 1;
+            }
+            layers.push(json!({
+                "type": "objectgroup",
+                "name": layer.name,
+                "x": 0,
+                "y": 0,
+                "opacity": 1,
+                "visible": true,
+                "objects": objects,
+            }));
+        }
+
+        {
+            let obj = json.as_object_mut().unwrap();
+            obj.insert("tilesets".to_string(), Value::Array(tilesets));
+            obj.insert("layers".to_string(), Value::Array(layers));
+        }
+
+        serde_json::to_writer_pretty(&mut output, &json)?;
+        Ok(())
+    }
+
+    /// Write the map in Tiled's TMX format with CSV encoded tile layers.
+    pub fn save_tmx<W: Write>(&self, mut output: W) -> Result<(), PrefabError> {
+        let (size, grids) = self.layer_grids()?;
+
+        writeln!(output, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        write!(output, r#"<map version="1.0""#)?;
+        match self.orientation {
+            TiledOrientation::Orthogonal => write!(output, r#" orientation="orthogonal""#)?,
+            TiledOrientation::Hexagonal { stagger_axis, stagger_index, side_length } => {
+                write!(output,
+                       r#" orientation="hexagonal" staggeraxis="{}" staggerindex="{}""#,
+                       axis_name(stagger_axis),
+                       index_name(stagger_index))?;
+                write!(output, r#" hexsidelength="{}""#, side_length)?;
+            }
+        }
+        writeln!(output,
+                 r#" renderorder="right-down" width="{}" height="{}""#,
+                 size.width,
+                 size.height)?;
+        writeln!(output,
+                 r#"     tilewidth="{}" tileheight="{}">"#,
+                 self.tile_size.width,
+                 self.tile_size.height)?;
+
+        for &(first_gid, ref source) in &self.tilesets {
+            writeln!(output,
+                     r#" <tileset firstgid="{}" source="{}"/>"#,
+                     first_gid,
+                     escape(source))?;
+        }
+
+        for (layer, gids) in self.layers.iter().zip(grids) {
+            writeln!(output,
+                     r#" <layer name="{}" width="{}" height="{}">"#,
+                     escape(&layer.name),
+                     size.width,
+                     size.height)?;
+            writeln!(output, r#"  <data encoding="csv">"#)?;
+            let rows: Vec<String> = gids.chunks(size.width as usize)
+                                        .map(|row| {
+                                            row.iter()
+                                               .map(|x| x.to_string())
+                                               .collect::<Vec<_>>()
+                                               .join(",")
+                                        })
+                                        .collect();
+            writeln!(output, "{}", rows.join(",\n"))?;
+            writeln!(output, "  </data>")?;
+            writeln!(output, " </layer>")?;
+        }
+
+        let mut id = 1;
+        for layer in &self.object_layers {
+            writeln!(output, r#" <objectgroup name="{}">"#, escape(&layer.name))?;
+            for obj in &layer.objects {
+                write!(output,
+                       r#"  <object id="{}" name="{}" type="{}" x="{}" y="{}""#,
+                       id,
+                       escape(&obj.name),
+                       escape(&obj.kind),
+                       obj.pixel_pos.x,
+                       obj.pixel_pos.y)?;
+                id += 1;
+                if obj.properties.is_empty() {
+                    writeln!(output, "/>")?;
+                    continue;
+                }
+                writeln!(output, ">")?;
+                writeln!(output, "   <properties>")?;
+                for (k, v) in &obj.properties {
+                    writeln!(output,
+                             r#"    <property name="{}" value="{}"/>"#,
+                             escape(k),
+                             escape(v))?;
+                }
+                writeln!(output, "   </properties>")?;
+                writeln!(output, "  </object>")?;
+            }
+            writeln!(output, " </objectgroup>")?;
+        }
+
+        writeln!(output, "</map>")?;
+        Ok(())
+    }
+
+    /// Build a layer from a row-major grid of global tile ids.
+    fn build_layer(&self,
+                   name: String,
+                   width: u32,
+                   gids: &[u32])
+                   -> Result<TiledLayer, PrefabError> {
+        if width == 0 && !gids.is_empty() {
+            return Err(bad("Tile layer has no width"));
+        }
+
+        let mut cells = Vec::new();
+        for (i, &gid) in gids.iter().enumerate() {
+            if gid & !GID_FLAG_MASK == 0 {
+                continue;
+            }
+            let offset = Point2D::new((i as u32 % width) as i32, (i as u32 / width) as i32);
+            cells.push((self.orientation.to_axial(offset), gid));
+        }
+
+        let origin = Point2D::new(cells.iter().map(|&(p, _)| p.x).min().unwrap_or(0),
+                                  cells.iter().map(|&(p, _)| p.y).min().unwrap_or(0));
+
+        Ok(TiledLayer {
+            name: name,
+            origin: origin,
+            tiles: cells.into_iter().collect(),
+        })
+    }
+
+    fn build_object(&self,
+                    name: &str,
+                    kind: &str,
+                    pixel_pos: Point2D<f32>,
+                    properties: BTreeMap<String, String>)
+                    -> TiledObject {
+        TiledObject {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            pixel_pos: pixel_pos,
+            pos: self.orientation.to_axial(self.orientation.pixel_to_offset(self.tile_size,
+                                                                            pixel_pos)),
+            properties: properties,
+        }
+    }
+
+    /// Lay out the tile layers into row-major grids of global tile ids.
+    fn layer_grids(&self) -> Result<(Size2D<u32>, Vec<Vec<u32>>), PrefabError> {
+        let mut width = 0;
+        let mut height = 0;
+        for layer in &self.layers {
+            for (p, _) in layer.tiles.iter() {
+                let offset = self.layer_offset(layer, p);
+                if offset.x < 0 || offset.y < 0 {
+                    return Err(bad("Layer has tiles at negative Tiled coordinates"));
+                }
+                width = max(width, offset.x + 1);
+                height = max(height, offset.y + 1);
+            }
+        }
+
+        let mut grids = Vec::new();
+        for layer in &self.layers {
+            let mut grid = vec![0; (width * height) as usize];
+            for (p, &gid) in layer.tiles.iter() {
+                let offset = self.layer_offset(layer, p);
+                grid[(offset.x + offset.y * width) as usize] = gid;
+            }
+            grids.push(grid);
+        }
+
+        Ok((Size2D::new(width as u32, height as u32), grids))
+    }
+
+    fn layer_offset(&self, layer: &TiledLayer, p: Point2D<i32>) -> Point2D<i32> {
+        self.orientation.from_axial(Point2D::new(p.x + layer.origin.x, p.y + layer.origin.y))
+    }
+}
+
+fn bad(msg: &str) -> PrefabError { PrefabError::Syntax(msg.to_string()) }
+
+fn parse_orientation(orientation: Option<&str>,
+                     stagger_axis: Option<&str>,
+                     stagger_index: Option<&str>,
+                     side_length: Option<u32>)
+                     -> Result<TiledOrientation, PrefabError> {
+    match orientation {
+        Some("orthogonal") => Ok(TiledOrientation::Orthogonal),
+        Some("hexagonal") => {
+            let stagger_axis = match stagger_axis {
+                Some("x") => StaggerAxis::X,
+                Some("y") => StaggerAxis::Y,
+                _ => return Err(bad("Bad stagger axis")),
+            };
+            let stagger_index = match stagger_index {
+                Some("odd") => StaggerIndex::Odd,
+                Some("even") => StaggerIndex::Even,
+                _ => return Err(bad("Bad stagger index")),
+            };
+            Ok(TiledOrientation::Hexagonal {
+                stagger_axis: stagger_axis,
+                stagger_index: stagger_index,
+                side_length: side_length.unwrap_or(0),
+            })
+        }
+        Some(x) => Err(PrefabError::Syntax(format!("Unsupported orientation {}", x))),
+        None => Err(bad("Missing orientation")),
+    }
+}
+
+fn axis_name(axis: StaggerAxis) -> &'static str {
+    match axis {
+        StaggerAxis::X => "x",
+        StaggerAxis::Y => "y",
+    }
+}
+
+fn index_name(index: StaggerIndex) -> &'static str {
+    match index {
+        StaggerIndex::Odd => "odd",
+        StaggerIndex::Even => "even",
+    }
+}
+
+/// Decode base64 layer data into global tile ids.
+fn decode_base64(data: &str, compression: &str) -> Result<Vec<u32>, PrefabError> {
+    let bytes = match base64::decode(data.trim()) {
+        Ok(bytes) => bytes,
+        Err(e) => return Err(PrefabError::Syntax(format!("Bad base64 data: {}", e))),
+    };
+
+    let bytes = match compression {
+        "" => bytes,
+        "zlib" => {
+            let mut buf = Vec::new();
+            ZlibDecoder::new(&bytes[..]).read_to_end(&mut buf)?;
+            buf
+        }
+        "gzip" => {
+            let mut buf = Vec::new();
+            GzDecoder::new(&bytes[..])?.read_to_end(&mut buf)?;
+            buf
+        }
+        x => return Err(PrefabError::Syntax(format!("Unsupported compression {}", x))),
+    };
+
+    if bytes.len() % 4 != 0 {
+        return Err(bad("Layer data length is not a multiple of 4"));
+    }
+
+    Ok(bytes.chunks(4)
+            .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+            .collect())
+}
+
+fn json_str<'a>(json: &'a Value, key: &str) -> Option<&'a str> {
+    json.get(key).and_then(|x| x.as_str())
+}
+
+fn json_u32(json: &Value, key: &str) -> Option<u32> {
+    json.get(key).and_then(|x| x.as_u64()).map(|x| x as u32)
+}
+
+fn json_f32(json: &Value, key: &str) -> f32 {
+    json.get(key).and_then(|x| x.as_f64()).unwrap_or(0.0) as f32
+}
+
+fn json_to_string(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) => s.clone(),
+        ref x => x.to_string(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use euclid::{Point2D, Size2D};
+    use hex::HexGeom;
+    use prefab::Prefab;
+    use super::{StaggerAxis, StaggerIndex, TiledMap, TiledOrientation};
+
+    fn hex(axis: StaggerAxis, index: StaggerIndex) -> TiledOrientation {
+        TiledOrientation::Hexagonal {
+            stagger_axis: axis,
+            stagger_index: index,
+            side_length: 8,
+        }
+    }
+
+    #[test]
+    fn test_hex_coordinates() {
+        for &axis in &[StaggerAxis::X, StaggerAxis::Y] {
+            for &index in &[StaggerIndex::Odd, StaggerIndex::Even] {
+                let orientation = hex(axis, index);
+                for a in 0..36 {
+                    let a = Point2D::new(a % 6 - 2, a / 6 - 2);
+                    assert_eq!(a, orientation.from_axial(orientation.to_axial(a)));
+
+                    for b in 0..36 {
+                        let b = Point2D::new(b % 6 - 2, b / 6 - 2);
+
+                        // Positions in half-tile steps across the stagger and full steps along
+                        // it.
+                        let half_pos = |p: Point2D<i32>| {
+                            let (along, across) = match axis {
+                                StaggerAxis::X => (p.y, p.x),
+                                StaggerAxis::Y => (p.x, p.y),
+                            };
+                            let staggered = (across & 1 == 1) == (index == StaggerIndex::Odd);
+                            (along * 2 + if staggered { 1 } else { 0 }, across)
+                        };
+                        let (a1, a2) = half_pos(a);
+                        let (b1, b2) = half_pos(b);
+                        let adjacent = ((a1 - b1).abs() == 2 && a2 == b2) ||
+                                       ((a1 - b1).abs() == 1 && (a2 - b2).abs() == 1);
+
+                        let axial_a = orientation.to_axial(a);
+                        let axial_b = orientation.to_axial(b);
+                        let delta = Point2D::new(axial_a.x - axial_b.x, axial_a.y - axial_b.y);
+                        assert_eq!(adjacent, delta.hex_dist() == 1);
+                    }
+                }
+            }
+        }
+    }
+
+    static HEX_JSON: &'static str = r#"{
+        "orientation": "hexagonal",
+        "staggeraxis": "y",
+        "staggerindex": "odd",
+        "hexsidelength": 8,
+        "width": 3,
+        "height": 2,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "tilesets": [{"firstgid": 1, "source": "terrain.tsx"}],
+        "layers": [
+            {"type": "tilelayer", "name": "ground", "width": 3, "height": 2,
+             "data": [1, 1, 2, 0, 3, 1]},
+            {"type": "tilelayer", "name": "packed", "width": 3, "height": 2,
+             "encoding": "base64", "data": "AQAAAAEAAAACAAAAAAAAAAMAAAABAAAA"},
+            {"type": "objectgroup", "name": "things", "objects": [
+                {"name": "chest", "type": "item", "x": 20, "y": 14,
+                 "properties": [{"name": "gold", "type": "int", "value": 12}]},
+                {"name": "door", "type": "", "x": 4, "y": 2,
+                 "properties": {"locked": true}}
+            ]}
+        ]
+    }"#;
+
+    #[test]
+    fn test_load_json() {
+        let map = TiledMap::load_json(HEX_JSON.as_bytes()).unwrap();
+        assert_eq!(hex(StaggerAxis::Y, StaggerIndex::Odd), map.orientation);
+        assert_eq!(vec![(1, "terrain.tsx".to_string())], map.tilesets);
+        assert_eq!(2, map.layers.len());
+
+        // Odd rows are shifted right, so the second row lines up like a text hexmap.
+        let expected = Prefab::from_text_hexmap("
+1 1 2
+ . 3 1
+")
+                           .map(|c| c.to_digit(10).unwrap_or(0));
+        let expected: Prefab<u32> = expected.iter()
+                                            .filter(|&(_, &c)| c != 0)
+                                            .map(|(p, &c)| (p, c))
+                                            .collect();
+        assert_eq!(expected, map.layers[0].tiles);
+        assert_eq!(expected, map.layers[1].tiles);
+        assert_eq!(Point2D::new(0, 0), map.layers[0].origin);
+
+        let objects = &map.object_layers[0].objects;
+        assert_eq!("chest", objects[0].name);
+        assert_eq!("item", objects[0].kind);
+        assert_eq!(Some(&"12".to_string()), objects[0].properties.get("gold"));
+        // Second row, first column.
+        assert_eq!(Point2D::new(1, 1), objects[0].pos);
+        assert_eq!(Some(&"true".to_string()), objects[1].properties.get("locked"));
+        assert_eq!(Point2D::new(0, 0), objects[1].pos);
+    }
+
+    #[test]
+    fn test_load_tmx() {
+        let tmx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.0" orientation="hexagonal" width="3" height="2" tilewidth="16" tileheight="16"
+     hexsidelength="8" staggeraxis="y" staggerindex="odd">
+ <tileset firstgid="1" name="embedded" tilewidth="16" tileheight="16">
+  <tile id="0"><properties><property name="wall" value="true"/></properties></tile>
+ </tileset>
+ <layer name="ground" width="3" height="2">
+  <data encoding="csv">
+1,1,2,
+0,3,1
+</data>
+ </layer>
+ <layer name="zipped" width="3" height="2">
+  <data encoding="base64" compression="zlib">eJxjZGBgYARiJgYIYIbyAQCAAAk=</data>
+ </layer>
+ <layer name="xml" width="3" height="2">
+  <data>
+   <tile gid="1"/><tile gid="1"/><tile gid="2"/><tile/><tile gid="3"/><tile gid="1"/>
+  </data>
+ </layer>
+ <objectgroup name="things">
+  <object id="1" name="chest" type="item" x="20" y="14">
+   <properties>
+    <property name="gold" type="int" value="12"/>
+   </properties>
+  </object>
+ </objectgroup>
+</map>"#;
+        let map = TiledMap::load_tmx(tmx.as_bytes()).unwrap();
+        let json_map = TiledMap::load_json(HEX_JSON.as_bytes()).unwrap();
+
+        assert_eq!(json_map.orientation, map.orientation);
+        assert!(map.tilesets.is_empty());
+        assert_eq!(3, map.layers.len());
+        for layer in &map.layers {
+            assert_eq!(json_map.layers[0].tiles, layer.tiles);
+        }
+        assert_eq!("things", map.object_layers[0].name);
+        assert_eq!(json_map.object_layers[0].objects[0],
+                   map.object_layers[0].objects[0]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &orientation in &[TiledOrientation::Orthogonal,
+                              hex(StaggerAxis::X, StaggerIndex::Even),
+                              hex(StaggerAxis::Y, StaggerIndex::Odd)] {
+            let mut map = TiledMap::new(orientation, Size2D::new(16, 16));
+            let tiles = Prefab::from_text_hexmap("
+    1 1 1
+   1 2 2
+  1 1 3
+ 1 4 4
+")
+                            .map(|c| c.to_digit(10).unwrap());
+            map.push_layer("ground", tiles.clone());
+            map.tilesets.push((1, "terrain.tsx".to_string()));
+
+            let mut json = Vec::new();
+            map.save_json(&mut json).unwrap();
+            let map2 = TiledMap::load_json(&json[..]).unwrap();
+            assert_eq!(map.orientation, map2.orientation);
+            assert_eq!(map.tilesets, map2.tilesets);
+            assert_eq!(tiles, map2.layers[0].tiles);
+            assert_eq!(map.layers[0].origin, map2.layers[0].origin);
+
+            let mut tmx = Vec::new();
+            map.save_tmx(&mut tmx).unwrap();
+            let map3 = TiledMap::load_tmx(&tmx[..]).unwrap();
+            assert_eq!(map.orientation, map3.orientation);
+            assert_eq!(tiles, map3.layers[0].tiles);
+            assert_eq!(map.layers[0].origin, map3.layers[0].origin);
+        }
+    }
+}
@@ -0,0 +1,320 @@
+//! Placing hand-made vault prefabs into generated levels.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use rand::Rng;
+use euclid::Point2D;
+use calx_alg::WeightedChoice;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+use pattern::PatternTransform;
+use search::{GridNode, MapNode};
+
+/// A vault prefab that can be placed in a level.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Vault<T> {
+    /// Cells of the vault. Empty cells leave the level unchanged.
+    pub prefab: Prefab<T>,
+    /// Relative likelihood of choosing this vault.
+    pub weight: u32,
+}
+
+impl<T> Vault<T> {
+    pub fn new(prefab: Prefab<T>, weight: u32) -> Vault<T> {
+        Vault {
+            prefab: prefab,
+            weight: weight,
+        }
+    }
+}
+
+/// A vault placed in a level.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct VaultPlacement {
+    /// Index of the vault in the candidate list.
+    pub vault: usize,
+    /// Level position of the origin of the transformed vault.
+    pub pos: Point2D<i32>,
+    /// Transformation applied to the vault.
+    pub transform: PatternTransform,
+}
+
+/// Places vaults at random legal positions in a level.
+///
+/// A position is legal when the vault fits inside the level, when `compatible(level_cell,
+/// vault_cell)` holds for every cell of the vault, when the vault does not overlap a vault placed
+/// earlier and when the placement keeps the passable cells of the level connected. Cells that
+/// could reach each other before the placement must still reach each other afterwards, and the
+/// vault must not add passable areas that aren't joined to any earlier passable cells. The level
+/// does not need to be connected to begin with.
+pub struct VaultPlacer<C, P> {
+    layout: MapLayout,
+    compatible: C,
+    passable: P,
+}
+
+impl<C, P> VaultPlacer<C, P> {
+    /// Create a new placer.
+    ///
+    /// The `compatible` function is called with the level cell, which is `None` where the level
+    /// prefab has no cell, and the vault cell that would replace it. The `passable` function
+    /// tells the cells that movement can pass through for the connectivity check.
+    pub fn new(layout: MapLayout, compatible: C, passable: P) -> VaultPlacer<C, P> {
+        VaultPlacer {
+            layout: layout,
+            compatible: compatible,
+            passable: passable,
+        }
+    }
+
+    /// Place up to `count` vaults in the level.
+    ///
+    /// Vaults are chosen by weight and tried under every rotation and mirroring. A vault that
+    /// has no legal positions left is not chosen again. Returns the new level and the placements
+    /// in the order they were made.
+    pub fn place<T, R>(&self,
+                       rng: &mut R,
+                       level: &Prefab<T>,
+                       vaults: &[Vault<T>],
+                       count: usize)
+                       -> (Prefab<T>, Vec<VaultPlacement>)
+        where T: Clone + Eq + Hash,
+              R: Rng,
+              C: Fn(Option<&T>, &T) -> bool,
+              P: Fn(&T) -> bool
+    {
+        // Distinct transformed versions of every vault.
+        let variants: Vec<Vec<(PatternTransform, Prefab<T>)>> =
+            vaults.iter()
+                  .map(|v| {
+                      let mut ret: Vec<(PatternTransform, Prefab<T>)> = Vec::new();
+                      for t in PatternTransform::all(self.layout) {
+                          let stamp = t.apply(&v.prefab, self.layout);
+                          if ret.iter().all(|&(_, ref s)| s != &stamp) {
+                              ret.push((t, stamp));
+                          }
+                      }
+                      ret
+                  })
+                  .collect();
+
+        let mut level = level.clone();
+        let mut occupied = HashSet::new();
+        let mut exhausted = vec![false; vaults.len()];
+        let mut placements = Vec::new();
+        let mut areas = self.areas(&level);
+
+        while placements.len() < count {
+            let vault = match (0..vaults.len())
+                                  .filter(|&i| !exhausted[i] && vaults[i].weight > 0)
+                                  .weighted_choice(rng, |&i| vaults[i].weight as f32) {
+                Some(i) => i,
+                None => break,
+            };
+
+            let mut candidates = Vec::new();
+            for &(t, ref stamp) in &variants[vault] {
+                let dim = level.dim();
+                let stamp_dim = stamp.dim();
+                for y in 0..(dim.height as i32 - stamp_dim.height as i32 + 1) {
+                    for x in 0..(dim.width as i32 - stamp_dim.width as i32 + 1) {
+                        let pos = Point2D::new(x, y);
+                        if self.fits(&level, &occupied, pos, stamp) {
+                            candidates.push((pos, t, stamp));
+                        }
+                    }
+                }
+            }
+            rng.shuffle(&mut candidates);
+
+            let mut placed = false;
+            for (pos, t, stamp) in candidates {
+                let new_level = level.blit(pos, stamp, |_, e| e.clone());
+                let new_areas = self.areas(&new_level);
+                if !stays_connected(&areas, &new_areas) {
+                    continue;
+                }
+
+                for (p, _) in stamp.iter() {
+                    occupied.insert(p + pos);
+                }
+                level = new_level;
+                areas = new_areas;
+                placements.push(VaultPlacement {
+                    vault: vault,
+                    pos: pos,
+                    transform: t,
+                });
+                placed = true;
+                break;
+            }
+
+            if !placed {
+                exhausted[vault] = true;
+            }
+        }
+
+        (level, placements)
+    }
+
+    fn fits<T>(&self,
+               level: &Prefab<T>,
+               occupied: &HashSet<Point2D<i32>>,
+               pos: Point2D<i32>,
+               stamp: &Prefab<T>)
+               -> bool
+        where T: Clone + Eq + Hash,
+              C: Fn(Option<&T>, &T) -> bool
+    {
+        stamp.iter().all(|(p, e)| {
+            let p = p + pos;
+            !occupied.contains(&p) && (self.compatible)(level.get(p), e)
+        })
+    }
+
+    /// Label every passable cell of a level with the index of its separate passable area.
+    fn areas<T>(&self, level: &Prefab<T>) -> HashMap<Point2D<i32>, usize>
+        where T: Clone + Eq + Hash,
+              P: Fn(&T) -> bool
+    {
+        let mut ret = HashMap::new();
+        let mut n_areas = 0;
+        for (p, e) in level.iter() {
+            if ret.contains_key(&p) || !(self.passable)(e) {
+                continue;
+            }
+            ret.insert(p, n_areas);
+            let mut edge = vec![MapNode::new(p, self.layout)];
+            while let Some(node) = edge.pop() {
+                for n in node.neighbors() {
+                    if !ret.contains_key(&n.pos()) &&
+                       level.get(n.pos()).map_or(false, |e| (self.passable)(e)) {
+                        ret.insert(n.pos(), n_areas);
+                        edge.push(n);
+                    }
+                }
+            }
+            n_areas += 1;
+        }
+        ret
+    }
+}
+
+/// Return whether no area of `old` is split up in `new` and every area of `new` contains cells
+/// that were passable in `old`.
+fn stays_connected(old: &HashMap<Point2D<i32>, usize>,
+                   new: &HashMap<Point2D<i32>, usize>)
+                   -> bool {
+    // The area in the new level of every old area that still has passable cells.
+    let mut moved_to = HashMap::new();
+    for (p, &a) in old {
+        if let Some(&b) = new.get(p) {
+            if *moved_to.entry(a).or_insert(b) != b {
+                return false;
+            }
+        }
+    }
+
+    let kept: HashSet<usize> = moved_to.values().cloned().collect();
+    new.values().all(|b| kept.contains(b))
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{SeedableRng, XorShiftRng};
+    use euclid::Point2D;
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use super::{Vault, VaultPlacer};
+
+    fn level() -> Prefab<char> {
+        Prefab::from_text_map("
+##########
+#........#
+##########
+##########
+##########
+")
+    }
+
+    #[test]
+    fn test_rotation() {
+        // The door must go on floor and everything else on wall, so the vault only fits upside
+        // down below the corridor.
+        let vault = Vault::new(Prefab::from_text_map("
+###
+#$#
+ +
+"),
+                               1);
+        let placer = VaultPlacer::new(MapLayout::Dense,
+                                      |level: Option<&char>, &vault: &char| {
+                                          let wanted = if vault == '+' { '.' } else { '#' };
+                                          level == Some(&wanted)
+                                      },
+                                      |&c: &char| c != '#');
+
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let (map, placements) = placer.place(&mut rng, &level(), &[vault], 3);
+        assert!(placements.len() >= 2);
+        for p in &placements {
+            assert_eq!(2, p.transform.rotation);
+            assert_eq!(1, p.pos.y);
+            assert_eq!(Some(&'+'), map.get(Point2D::new(p.pos.x + 1, 1)));
+            assert_eq!(Some(&'$'), map.get(Point2D::new(p.pos.x + 1, 2)));
+        }
+        assert_eq!(level().dim(), map.dim());
+    }
+
+    #[test]
+    fn test_connectivity() {
+        let block = Vault::new(Prefab::from_text_map("#"), 1);
+        let statue = Vault::new(Prefab::from_text_map("&"), 0);
+        let placer = VaultPlacer::new(MapLayout::Dense,
+                                      |level: Option<&char>, _: &char| level == Some(&'.'),
+                                      |&c: &char| c != '#');
+
+        for seed in 1..10 {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 2, 3, 4]);
+            let (map, placements) = placer.place(&mut rng,
+                                                 &level(),
+                                                 &[block.clone(), statue.clone()],
+                                                 2);
+            assert_eq!(2, placements.len());
+            // Blocking the corridor is only allowed at the dead ends.
+            let mut xs: Vec<i32> = placements.iter().map(|p| p.pos.x).collect();
+            xs.sort();
+            assert!(xs == vec![1, 2] || xs == vec![1, 8] || xs == vec![7, 8],
+                    "Bad placement\n{}",
+                    map);
+            // Zero weight vaults are never placed.
+            assert!(placements.iter().all(|p| p.vault == 0));
+        }
+    }
+
+    #[test]
+    fn test_disconnected_level() {
+        // The wall of the vault goes in the corridor. Placing the vault at x = 4 would split the
+        // long corridor while joining the short one to it, which keeps the number of separate
+        // areas unchanged.
+        let level = Prefab::from_text_map("
+#########
+#.....#.#
+#########
+");
+        let vault = Vault::new(Prefab::from_text_map("#.."), 1);
+        let placer = VaultPlacer::new(MapLayout::Dense,
+                                      |level: Option<&char>, &vault: &char| {
+                                          vault != '#' || level == Some(&'.')
+                                      },
+                                      |&c: &char| c != '#');
+
+        for seed in 1..100 {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([seed, 2, 3, 4]);
+            let (map, placements) = placer.place(&mut rng, &level, &[vault.clone()], 1);
+            assert_eq!(1, placements.len());
+            let corridor: String = (1..6).map(|x| *map.get(Point2D::new(x, 1)).unwrap()).collect();
+            assert!(!corridor.trim_matches('#').contains('#'), "Split corridor\n{}", map);
+        }
+    }
+}
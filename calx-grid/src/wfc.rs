@@ -0,0 +1,498 @@
+//! Wave Function Collapse map generation.
+//!
+//! An implementation of the overlapping model from https://github.com/mxgmn/WaveFunctionCollapse.
+//! The sample map is cut into N×N patterns and a new map is built so that every pattern in it also
+//! appears in the sample.
+//!
+//! Patterns are N×N blocks of map coordinates for both layouts. In the hex layout this is a
+//! rhombus of hex cells and the patterns overlap along the six hex directions instead of the four
+//! square directions.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::hash::Hash;
+use rand::Rng;
+use euclid::{Point2D, Size2D};
+use calx_alg::WeightedChoice;
+use prefab::Prefab;
+use prefab_file::MapLayout;
+use pattern::PatternTransform;
+
+/// Errors from Wave Function Collapse generation.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WfcError {
+    /// The sample map had no complete N×N patterns.
+    NoPatterns,
+    /// A pinned cell is outside the map or its value does not appear in any pattern.
+    BadPin(Point2D<i32>),
+    /// Every generation attempt ran into a cell that could not have any pattern.
+    Contradiction {
+        /// Number of attempts made.
+        attempts: usize,
+    },
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WfcError::NoPatterns => write!(f, "No patterns in sample"),
+            WfcError::BadPin(p) => {
+                write!(f, "Pinned cell at {:?} is outside the map or not in any pattern", p)
+            }
+            WfcError::Contradiction { attempts } => {
+                write!(f, "Contradiction in all {} attempts", attempts)
+            }
+        }
+    }
+}
+
+impl error::Error for WfcError {
+    fn description(&self) -> &str {
+        match *self {
+            WfcError::NoPatterns => "no patterns in sample",
+            WfcError::BadPin(_) => "bad pinned value",
+            WfcError::Contradiction { .. } => "generation contradiction",
+        }
+    }
+}
+
+/// Overlapping model for Wave Function Collapse.
+#[derive(Clone, Debug)]
+pub struct Wfc<T> {
+    values: Vec<T>,
+    /// Value indices of the N×N patterns in row-major order.
+    patterns: Vec<Vec<usize>>,
+    weights: Vec<f32>,
+    dirs: Vec<Point2D<i32>>,
+    /// For each direction and pattern, the patterns that can be next to it in that direction.
+    propagator: Vec<Vec<Vec<usize>>>,
+}
+
+impl<T: Clone + Eq + Hash> Wfc<T> {
+    /// Build the model from a sample map.
+    ///
+    /// If `symmetry` is set, the patterns are also extracted from every rotation and mirroring of
+    /// the sample. Blocks with empty cells are not used as patterns.
+    pub fn new(sample: &Prefab<T>,
+               layout: MapLayout,
+               n: u32,
+               symmetry: bool)
+               -> Result<Wfc<T>, WfcError> {
+        assert!(n > 0);
+        let n = n as i32;
+
+        let samples = if symmetry {
+            PatternTransform::all(layout).into_iter().map(|t| t.apply(sample, layout)).collect()
+        } else {
+            vec![sample.clone()]
+        };
+
+        // Scan in a fixed order so that the pattern indices and the generated maps don't depend on
+        // hash map iteration order.
+        let mut values = Vec::new();
+        let mut value_idx = HashMap::new();
+        let mut patterns = Vec::new();
+        let mut pattern_idx = HashMap::new();
+        let mut weights = Vec::new();
+
+        for sample in &samples {
+            let dim = sample.dim();
+            for y in 0..(dim.height as i32 - n + 1) {
+                for x in 0..(dim.width as i32 - n + 1) {
+                    let mut pattern = Vec::new();
+                    for v in 0..n {
+                        for u in 0..n {
+                            match sample.get(Point2D::new(x + u, y + v)) {
+                                Some(e) => {
+                                    let idx = *value_idx.entry(e.clone()).or_insert_with(|| {
+                                        values.push(e.clone());
+                                        values.len() - 1
+                                    });
+                                    pattern.push(idx);
+                                }
+                                None => break,
+                            }
+                        }
+                    }
+                    if pattern.len() != (n * n) as usize {
+                        continue;
+                    }
+
+                    let idx = *pattern_idx.entry(pattern.clone()).or_insert_with(|| {
+                        patterns.push(pattern);
+                        weights.push(0.0);
+                        patterns.len() - 1
+                    });
+                    weights[idx] += 1.0;
+                }
+            }
+        }
+
+        if patterns.is_empty() {
+            return Err(WfcError::NoPatterns);
+        }
+
+        // Opposite directions must be half the list apart.
+        let dirs: Vec<Point2D<i32>> = match layout {
+            MapLayout::Dense => {
+                vec![Point2D::new(0, -1),
+                     Point2D::new(1, 0),
+                     Point2D::new(0, 1),
+                     Point2D::new(-1, 0)]
+            }
+            MapLayout::Hex => ::hex::Dir6::iter().map(|d| d.to_v2()).collect(),
+        };
+
+        let agrees = |a: &[usize], b: &[usize], d: Point2D<i32>| {
+            for v in 0..n {
+                for u in 0..n {
+                    let (bu, bv) = (u - d.x, v - d.y);
+                    if bu < 0 || bv < 0 || bu >= n || bv >= n {
+                        continue;
+                    }
+                    if a[(u + v * n) as usize] != b[(bu + bv * n) as usize] {
+                        return false;
+                    }
+                }
+            }
+            true
+        };
+
+        let propagator = dirs.iter()
+                             .map(|&d| {
+                                 patterns.iter()
+                                         .map(|a| {
+                                             (0..patterns.len())
+                                                 .filter(|&b| agrees(a, &patterns[b], d))
+                                                 .collect()
+                                         })
+                                         .collect()
+                             })
+                             .collect();
+
+        Ok(Wfc {
+            values: values,
+            patterns: patterns,
+            weights: weights,
+            dirs: dirs,
+            propagator: propagator,
+        })
+    }
+
+    /// Return the number of distinct patterns in the model.
+    pub fn pattern_count(&self) -> usize { self.patterns.len() }
+
+    /// Generate a new map.
+    ///
+    /// The map covers the rectangle from origin to `size` in map coordinates. Pinned cells are
+    /// guaranteed to have the given values, pins outside the map are an error. If generation runs
+    /// into a contradiction, it is restarted up to `max_attempts` times.
+    pub fn generate<R: Rng>(&self,
+                            rng: &mut R,
+                            size: Size2D<u32>,
+                            pins: &[(Point2D<i32>, T)],
+                            max_attempts: usize)
+                            -> Result<Prefab<T>, WfcError> {
+        let mut pinned = Vec::new();
+        for &(p, ref value) in pins {
+            if p.x < 0 || p.y < 0 || p.x >= size.width as i32 || p.y >= size.height as i32 {
+                return Err(WfcError::BadPin(p));
+            }
+            match self.values.iter().position(|x| x == value) {
+                Some(idx) => pinned.push((p, idx)),
+                None => return Err(WfcError::BadPin(p)),
+            }
+        }
+
+        for _ in 0..max_attempts {
+            let mut wave = Wave::new(self, size);
+            if wave.run(rng, &pinned) {
+                let mut cells = Vec::new();
+                for y in 0..size.height as i32 {
+                    for x in 0..size.width as i32 {
+                        let p = Point2D::new(x, y);
+                        let value = &self.values[self.patterns[wave.chosen(p)][0]];
+                        cells.push((p, value.clone()));
+                    }
+                }
+                return Ok(cells.into_iter().collect());
+            }
+        }
+
+        Err(WfcError::Contradiction { attempts: max_attempts })
+    }
+}
+
+/// Generation state.
+struct Wave<'a, T: 'a> {
+    model: &'a Wfc<T>,
+    size: Size2D<u32>,
+    /// Possible patterns for each cell.
+    possible: Vec<Vec<bool>>,
+    /// Number of possible patterns for each cell.
+    count: Vec<usize>,
+    /// Sums of weights and weight * log(weight) of the possible patterns of each cell.
+    sum_w: Vec<f32>,
+    sum_wlogw: Vec<f32>,
+    /// For each cell, pattern and direction, how many patterns are still possible in the
+    /// neighboring cell opposite that direction that allow the pattern.
+    support: Vec<Vec<Vec<usize>>>,
+    /// Removed patterns whose removal has not been propagated yet.
+    stack: Vec<(usize, usize)>,
+    contradiction: bool,
+}
+
+impl<'a, T: Clone + Eq + Hash> Wave<'a, T> {
+    fn new(model: &'a Wfc<T>, size: Size2D<u32>) -> Wave<'a, T> {
+        let n_cells = (size.width * size.height) as usize;
+        let n_patterns = model.patterns.len();
+        let n_dirs = model.dirs.len();
+
+        let total_w: f32 = model.weights.iter().sum();
+        let total_wlogw: f32 = model.weights.iter().map(|w| w * w.ln()).sum();
+
+        // Support for b in direction d comes from the patterns a that allow b after them in
+        // direction d, which are the patterns that b allows in the opposite direction.
+        let support: Vec<Vec<usize>> =
+            (0..n_patterns)
+                .map(|b| {
+                    (0..n_dirs)
+                        .map(|d| model.propagator[(d + n_dirs / 2) % n_dirs][b].len())
+                        .collect()
+                })
+                .collect();
+
+        Wave {
+            model: model,
+            size: size,
+            possible: vec![vec![true; n_patterns]; n_cells],
+            count: vec![n_patterns; n_cells],
+            sum_w: vec![total_w; n_cells],
+            sum_wlogw: vec![total_wlogw; n_cells],
+            support: vec![support; n_cells],
+            stack: Vec::new(),
+            contradiction: false,
+        }
+    }
+
+    fn index(&self, p: Point2D<i32>) -> Option<usize> {
+        if p.x < 0 || p.y < 0 || p.x >= self.size.width as i32 || p.y >= self.size.height as i32 {
+            None
+        } else {
+            Some((p.x + p.y * self.size.width as i32) as usize)
+        }
+    }
+
+    fn pos(&self, i: usize) -> Point2D<i32> {
+        Point2D::new((i as u32 % self.size.width) as i32,
+                     (i as u32 / self.size.width) as i32)
+    }
+
+    fn chosen(&self, p: Point2D<i32>) -> usize {
+        let i = self.index(p).unwrap();
+        self.possible[i].iter().position(|&x| x).unwrap()
+    }
+
+    fn ban(&mut self, i: usize, pattern: usize) {
+        if !self.possible[i][pattern] {
+            return;
+        }
+        self.possible[i][pattern] = false;
+        self.count[i] -= 1;
+        let w = self.model.weights[pattern];
+        self.sum_w[i] -= w;
+        self.sum_wlogw[i] -= w * w.ln();
+        self.stack.push((i, pattern));
+        if self.count[i] == 0 {
+            self.contradiction = true;
+        }
+    }
+
+    fn propagate(&mut self) {
+        while let Some((i, a)) = self.stack.pop() {
+            let p = self.pos(i);
+            for d in 0..self.model.dirs.len() {
+                let dir = self.model.dirs[d];
+                let j = match self.index(Point2D::new(p.x + dir.x, p.y + dir.y)) {
+                    Some(j) => j,
+                    None => continue,
+                };
+                for &b in &self.model.propagator[d][a] {
+                    self.support[j][b][d] -= 1;
+                    if self.support[j][b][d] == 0 {
+                        self.ban(j, b);
+                    }
+                }
+            }
+            if self.contradiction {
+                return;
+            }
+        }
+    }
+
+    /// Run the generation to completion, return false on contradiction.
+    fn run<R: Rng>(&mut self, rng: &mut R, pins: &[(Point2D<i32>, usize)]) -> bool {
+        for &(p, value) in pins {
+            let i = self.index(p).expect("Pin outside the map");
+            for pattern in 0..self.model.patterns.len() {
+                if self.model.patterns[pattern][0] != value {
+                    self.ban(i, pattern);
+                }
+            }
+        }
+        self.propagate();
+
+        while !self.contradiction {
+            // Observe the undecided cell with the lowest entropy.
+            let mut best = None;
+            let mut best_entropy = ::std::f32::INFINITY;
+            for i in 0..self.count.len() {
+                if self.count[i] < 2 {
+                    continue;
+                }
+                let entropy = self.sum_w[i].ln() - self.sum_wlogw[i] / self.sum_w[i] +
+                              rng.gen::<f32>() * 1e-4;
+                if entropy < best_entropy {
+                    best_entropy = entropy;
+                    best = Some(i);
+                }
+            }
+
+            let i = match best {
+                Some(i) => i,
+                None => return true,
+            };
+
+            let choice = {
+                let possible = &self.possible[i];
+                let weights = &self.model.weights;
+                (0..possible.len())
+                    .filter(|&x| possible[x])
+                    .weighted_choice(rng, |&x| weights[x])
+                    .unwrap()
+            };
+            for pattern in 0..self.model.patterns.len() {
+                if pattern != choice {
+                    self.ban(i, pattern);
+                }
+            }
+            self.propagate();
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use rand::{SeedableRng, XorShiftRng};
+    use euclid::{Point2D, Size2D};
+    use prefab::Prefab;
+    use prefab_file::MapLayout;
+    use super::{Wfc, WfcError};
+
+    /// Collect every N×N block of a map.
+    fn blocks(map: &Prefab<char>, n: i32) -> HashSet<Vec<char>> {
+        let mut ret = HashSet::new();
+        let dim = map.dim();
+        for y in 0..(dim.height as i32 - n + 1) {
+            for x in 0..(dim.width as i32 - n + 1) {
+                let block: Vec<char> =
+                    (0..n * n)
+                        .filter_map(|i| map.get(Point2D::new(x + i % n, y + i / n)))
+                        .cloned()
+                        .collect();
+                if block.len() == (n * n) as usize {
+                    ret.insert(block);
+                }
+            }
+        }
+        ret
+    }
+
+    #[test]
+    fn test_generate() {
+        let sample = Prefab::from_text_map("
+#########
+#...#...#
+#.#.#.#.#
+#.......#
+#########
+");
+        for &layout in &[MapLayout::Dense, MapLayout::Hex] {
+            let model = Wfc::new(&sample, layout, 2, false).unwrap();
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            let map = model.generate(&mut rng, Size2D::new(12, 10), &[], 20).unwrap();
+            assert_eq!(Size2D::new(12, 10), map.dim());
+
+            // Every block of the output must come from the sample.
+            let known = blocks(&sample, 2);
+            for block in blocks(&map, 2) {
+                assert!(known.contains(&block), "Unknown block {:?}", block);
+            }
+
+            // Same seed, same map.
+            let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+            assert_eq!(map,
+                       model.generate(&mut rng, Size2D::new(12, 10), &[], 20).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_symmetry() {
+        let sample = Prefab::from_text_map("
+###
+#..
+#..
+");
+        let plain = Wfc::new(&sample, MapLayout::Dense, 2, false).unwrap();
+        let symmetric = Wfc::new(&sample, MapLayout::Dense, 2, true).unwrap();
+        assert!(symmetric.pattern_count() > plain.pattern_count());
+    }
+
+    #[test]
+    fn test_pins() {
+        let sample = Prefab::from_text_map("
+#####
+#...#
+#.@.#
+#...#
+#####
+");
+        let model = Wfc::new(&sample, MapLayout::Dense, 2, false).unwrap();
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let map = model.generate(&mut rng, Size2D::new(8, 8), &[(Point2D::new(3, 4), '@')], 20)
+                       .unwrap();
+        assert_eq!(Some(&'@'), map.get(Point2D::new(3, 4)));
+
+        assert_eq!(Err(WfcError::BadPin(Point2D::new(0, 0))),
+                   model.generate(&mut rng, Size2D::new(8, 8), &[(Point2D::new(0, 0), 'x')], 20));
+        assert_eq!(Err(WfcError::BadPin(Point2D::new(8, 2))),
+                   model.generate(&mut rng, Size2D::new(8, 8), &[(Point2D::new(8, 2), '@')], 20));
+        assert_eq!(Err(WfcError::BadPin(Point2D::new(-1, 0))),
+                   model.generate(&mut rng, Size2D::new(8, 8), &[(Point2D::new(-1, 0), '@')], 20));
+    }
+
+    #[test]
+    fn test_contradiction() {
+        // Rows alternate, so two rows of the same kind on top of each other are impossible.
+        let sample = Prefab::from_text_map("
+aaaa
+bbbb
+aaaa
+bbbb
+");
+        let model = Wfc::new(&sample, MapLayout::Dense, 2, false).unwrap();
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        assert_eq!(Err(WfcError::Contradiction { attempts: 3 }),
+                   model.generate(&mut rng,
+                                  Size2D::new(4, 4),
+                                  &[(Point2D::new(0, 0), 'a'), (Point2D::new(0, 1), 'a')],
+                                  3));
+
+        assert_eq!(Err(WfcError::NoPatterns),
+                   Wfc::new(&sample, MapLayout::Dense, 5, false).map(|_| ()));
+    }
+}